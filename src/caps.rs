@@ -0,0 +1,338 @@
+// IRCv3 capability negotiation.
+//
+// With "caps" set in config.json the client opens registration with
+// CAP LS 302, requests whichever of those caps the server offers and ends
+// negotiation with CAP END once every request is ACKed or NAKed. Servers can
+// add or drop caps later (cap-notify NEW/DEL), which is followed as well.
+// Handlers see the result through ctx.caps. When SASL is configured CAP END
+// waits until authentication is over (sasl.rs).
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, RwLock};
+
+use crate::message::IrcMessage;
+
+// room for "CAP REQ :" and the rest of the line
+const MAX_REQ_LENGTH: usize = 400;
+
+// Shared view of the caps: what the server offers and what is enabled.
+#[derive(Debug, Default, Clone)]
+pub struct Caps {
+    state: Arc<RwLock<CapState>>,
+}
+
+#[derive(Debug, Default)]
+struct CapState {
+    // name -> value, e.g. "sasl" -> "PLAIN,EXTERNAL"
+    available: BTreeMap<String, String>,
+    enabled: BTreeSet<String>,
+}
+
+impl Caps {
+    pub fn is_enabled(&self, cap: &str) -> bool {
+        self.read(|state| state.enabled.contains(cap))
+    }
+
+    pub fn enabled(&self) -> Vec<String> {
+        self.read(|state| state.enabled.iter().cloned().collect())
+    }
+
+    pub fn is_available(&self, cap: &str) -> bool {
+        self.read(|state| state.available.contains_key(cap))
+    }
+
+    // the value the server advertised with the cap, empty when it had none
+    pub fn value(&self, cap: &str) -> Option<String> {
+        self.read(|state| state.available.get(cap).cloned())
+    }
+
+    fn read<T>(&self, f: impl FnOnce(&CapState) -> T) -> T {
+        f(&self.state.read().unwrap_or_else(|err| err.into_inner()))
+    }
+
+    fn write<T>(&self, f: impl FnOnce(&mut CapState) -> T) -> T {
+        f(&mut self.state.write().unwrap_or_else(|err| err.into_inner()))
+    }
+}
+
+// What one CAP line changed.
+#[derive(Debug, Default)]
+pub(crate) struct CapUpdate {
+    pub(crate) lines: Vec<String>,
+    pub(crate) added: Vec<String>,
+    pub(crate) removed: Vec<String>,
+}
+
+pub(crate) struct CapNegotiator {
+    wanted: Vec<String>,
+    caps: Caps,
+    // CAP REQs without an ACK or NAK yet
+    pending: usize,
+    // true from CAP LS until CAP END
+    negotiating: bool,
+    // SASL is configured: hold CAP END once the server ACKs "sasl"
+    sasl: bool,
+    holding: bool,
+}
+
+impl CapNegotiator {
+    pub(crate) fn new(wanted: Vec<String>, sasl: bool, caps: Caps) -> Self {
+        let mut negotiator = CapNegotiator { wanted: Vec::new(), caps, pending: 0, negotiating: false, sasl, holding: false };
+        negotiator.set_wanted(wanted, sasl);
+        negotiator
+    }
+
+    // Caps from a reloaded config, asked for from the next start() on. A
+    // negotiation in progress carries on as it was.
+    pub(crate) fn set_wanted(&mut self, mut wanted: Vec<String>, sasl: bool) {
+        if sasl && !wanted.iter().any(|cap| cap == "sasl") {
+            wanted.push("sasl".to_string());
+        }
+        self.wanted = wanted;
+        self.sasl = sasl;
+    }
+
+    // Forget the last connection. Returns the line that opens negotiation,
+    // None when no caps are configured.
+    pub(crate) fn start(&mut self) -> Option<String> {
+        self.caps.write(|state| *state = CapState::default());
+        self.pending = 0;
+        self.holding = false;
+        self.negotiating = !self.wanted.is_empty();
+        self.negotiating.then(|| "CAP LS 302".to_string())
+    }
+
+    // :server CAP <nick> <subcommand> [*] :<caps>
+    pub(crate) fn handle(&mut self, msg: &IrcMessage) -> CapUpdate {
+        let mut update = CapUpdate::default();
+        let subcommand = msg.param(1).unwrap_or("").to_uppercase();
+        // a "*" before the list means more lines follow
+        let more = msg.param(2) == Some("*");
+        let list = msg.trailing.as_deref().or(msg.params.last().map(String::as_str)).unwrap_or("");
+
+        match subcommand.as_str() {
+            "LS" | "NEW" => {
+                self.caps.write(|state| {
+                    for cap in list.split_whitespace() {
+                        let (name, value) = cap.split_once('=').unwrap_or((cap, ""));
+                        state.available.insert(name.to_string(), value.to_string());
+                    }
+                });
+                if subcommand == "LS" && more {
+                    return update;
+                }
+                // only ask for what isn't enabled already
+                let wanted: Vec<&str> = self
+                    .wanted
+                    .iter()
+                    .map(String::as_str)
+                    .filter(|cap| self.caps.is_available(cap) && !self.caps.is_enabled(cap))
+                    .collect();
+                update.lines = request_lines(&wanted);
+                self.pending += update.lines.len();
+            },
+            "ACK" => {
+                self.caps.write(|state| {
+                    for cap in list.split_whitespace() {
+                        match cap.strip_prefix('-') {
+                            Some(cap) => {
+                                if state.enabled.remove(cap) {
+                                    update.removed.push(cap.to_string());
+                                }
+                            },
+                            None => {
+                                if state.enabled.insert(cap.to_string()) {
+                                    update.added.push(cap.to_string());
+                                }
+                                if cap == "sasl" && self.sasl && self.negotiating {
+                                    self.holding = true;
+                                }
+                            },
+                        }
+                    }
+                });
+                self.pending = self.pending.saturating_sub(1);
+            },
+            "NAK" => {
+                self.pending = self.pending.saturating_sub(1);
+            },
+            "DEL" => {
+                self.caps.write(|state| {
+                    for cap in list.split_whitespace() {
+                        state.available.remove(cap);
+                        if state.enabled.remove(cap) {
+                            update.removed.push(cap.to_string());
+                        }
+                    }
+                });
+            },
+            _ => {},
+        }
+
+        if !(subcommand == "LS" && more) && subcommand != "LIST" {
+            update.lines.extend(self.end());
+        }
+        update
+    }
+
+    // true from CAP LS until CAP END
+    pub(crate) fn is_negotiating(&self) -> bool {
+        self.negotiating
+    }
+
+    // true while CAP END waits for SASL
+    pub(crate) fn is_holding(&self) -> bool {
+        self.holding
+    }
+
+    // SASL is over, one way or the other; CAP END if nothing else is pending.
+    pub(crate) fn release(&mut self) -> Option<String> {
+        self.holding = false;
+        self.end()
+    }
+
+    fn end(&mut self) -> Option<String> {
+        if !self.negotiating || self.pending > 0 || self.holding {
+            return None;
+        }
+        self.negotiating = false;
+        Some("CAP END".to_string())
+    }
+}
+
+// CAP REQ lines for caps, split so no line gets too long
+fn request_lines(caps: &[&str]) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut current = String::new();
+    for cap in caps {
+        if !current.is_empty() && current.len() + 1 + cap.len() > MAX_REQ_LENGTH {
+            lines.push(format!("CAP REQ :{}", current));
+            current.clear();
+        }
+        if !current.is_empty() {
+            current.push(' ');
+        }
+        current.push_str(cap);
+    }
+    if !current.is_empty() {
+        lines.push(format!("CAP REQ :{}", current));
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn negotiator(wanted: &[&str], sasl: bool) -> CapNegotiator {
+        let wanted = wanted.iter().map(|cap| cap.to_string()).collect();
+        let mut negotiator = CapNegotiator::new(wanted, sasl, Caps::default());
+        assert_eq!(negotiator.start().as_deref(), Some("CAP LS 302"));
+        negotiator
+    }
+
+    fn handle(negotiator: &mut CapNegotiator, line: &str) -> CapUpdate {
+        negotiator.handle(&IrcMessage::parse(line).unwrap())
+    }
+
+    #[test]
+    fn nothing_wanted_nothing_negotiated() {
+        let mut negotiator = CapNegotiator::new(Vec::new(), false, Caps::default());
+        assert_eq!(negotiator.start(), None);
+        assert!(!negotiator.is_negotiating());
+    }
+
+    #[test]
+    fn multi_line_ls_waits_for_the_last_line() {
+        let mut negotiator = negotiator(&["server-time", "message-tags", "away-notify"], false);
+        let update = handle(&mut negotiator, ":srv CAP * LS * :server-time multi-prefix");
+        assert!(update.lines.is_empty());
+        let update = handle(&mut negotiator, ":srv CAP * LS :message-tags sasl=PLAIN,EXTERNAL");
+        assert_eq!(update.lines, vec!["CAP REQ :server-time message-tags"]);
+        assert_eq!(negotiator.caps.value("sasl").as_deref(), Some("PLAIN,EXTERNAL"));
+        assert!(!negotiator.caps.is_available("away-notify"));
+    }
+
+    #[test]
+    fn nothing_offered_ends_right_away() {
+        let mut negotiator = negotiator(&["server-time"], false);
+        let update = handle(&mut negotiator, ":srv CAP * LS :multi-prefix");
+        assert_eq!(update.lines, vec!["CAP END"]);
+        assert!(!negotiator.is_negotiating());
+    }
+
+    #[test]
+    fn long_requests_are_split() {
+        let wanted: Vec<String> = (0..40).map(|i| format!("vendor.example/capability-{:02}", i)).collect();
+        let wanted: Vec<&str> = wanted.iter().map(String::as_str).collect();
+        let mut negotiator = negotiator(&wanted, false);
+        let update = handle(&mut negotiator, &format!(":srv CAP * LS :{}", wanted.join(" ")));
+        assert!(update.lines.len() > 1);
+        let mut requested = Vec::new();
+        for line in &update.lines {
+            let caps = line.strip_prefix("CAP REQ :").unwrap();
+            assert!(caps.len() <= MAX_REQ_LENGTH, "{} bytes: {}", caps.len(), line);
+            requested.extend(caps.split(' '));
+        }
+        assert_eq!(requested, wanted);
+
+        // CAP END once every line has its answer
+        let count = update.lines.len();
+        for (i, line) in update.lines.iter().enumerate() {
+            let caps = line.strip_prefix("CAP REQ :").unwrap();
+            let reply = if i == 0 { "NAK" } else { "ACK" };
+            let update = handle(&mut negotiator, &format!(":srv CAP bot {} :{}", reply, caps));
+            if i + 1 < count {
+                assert!(update.lines.is_empty());
+            } else {
+                assert_eq!(update.lines, vec!["CAP END"]);
+            }
+        }
+        assert!(!negotiator.caps.is_enabled("vendor.example/capability-00"));
+        assert!(negotiator.caps.is_enabled("vendor.example/capability-39"));
+    }
+
+    #[test]
+    fn new_and_del_after_registration() {
+        let mut negotiator = negotiator(&["away-notify", "server-time"], false);
+        handle(&mut negotiator, ":srv CAP * LS :cap-notify server-time");
+        let update = handle(&mut negotiator, ":srv CAP bot ACK :server-time");
+        assert_eq!(update.added, vec!["server-time"]);
+        assert_eq!(update.lines, vec!["CAP END"]);
+
+        // asked for as soon as it shows up, without another CAP END
+        let update = handle(&mut negotiator, ":srv CAP bot NEW :away-notify");
+        assert_eq!(update.lines, vec!["CAP REQ :away-notify"]);
+        let update = handle(&mut negotiator, ":srv CAP bot ACK :away-notify");
+        assert_eq!(update.added, vec!["away-notify"]);
+        assert!(update.lines.is_empty());
+
+        let update = handle(&mut negotiator, ":srv CAP bot DEL :away-notify");
+        assert_eq!(update.removed, vec!["away-notify"]);
+        assert!(!negotiator.caps.is_enabled("away-notify"));
+        assert!(!negotiator.caps.is_available("away-notify"));
+        assert_eq!(negotiator.caps.enabled(), vec!["server-time"]);
+    }
+
+    #[test]
+    fn sasl_holds_cap_end_until_released() {
+        let mut negotiator = negotiator(&["server-time"], true);
+        let update = handle(&mut negotiator, ":srv CAP * LS :server-time sasl");
+        assert_eq!(update.lines, vec!["CAP REQ :server-time sasl"]);
+        let update = handle(&mut negotiator, ":srv CAP bot ACK :server-time sasl");
+        assert!(update.lines.is_empty());
+        assert!(negotiator.is_holding());
+        assert_eq!(negotiator.release().as_deref(), Some("CAP END"));
+        assert!(!negotiator.is_holding());
+        assert!(!negotiator.is_negotiating());
+    }
+
+    #[test]
+    fn sasl_refused_does_not_hold() {
+        let mut negotiator = negotiator(&[], true);
+        handle(&mut negotiator, ":srv CAP * LS :sasl");
+        let update = handle(&mut negotiator, ":srv CAP bot NAK :sasl");
+        assert_eq!(update.lines, vec!["CAP END"]);
+        assert!(!negotiator.is_holding());
+    }
+}
@@ -0,0 +1,195 @@
+// The "channels" list in config.json. Each entry is either just a name or
+// an object with settings:
+//
+//   "channels": ["%#Lobby", {"name": "#secret", "key": "hunter2", "log": false,
+//                "greeting": "Welcome {nick}!", "auto_rejoin": false}]
+//
+//   key          channel key (+k) to join with
+//   log          print the channel's messages in the terminal (default on)
+//   greeting     sent to the channel whenever someone else joins, {nick} is their nick
+//   auto_rejoin  join again after being kicked (default on)
+
+use serde::{Deserialize, Serialize};
+
+use crate::event::is_channel;
+
+// longest JOIN line we send, leaving room under the 512 byte limit
+const MAX_JOIN_LENGTH: usize = 400;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "ChannelEntry")]
+pub struct ChannelConfig {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    pub log: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub greeting: Option<String>,
+    pub auto_rejoin: bool,
+}
+
+impl ChannelConfig {
+    pub fn new(name: &str) -> Self {
+        ChannelConfig { name: name.to_string(), key: None, log: true, greeting: None, auto_rejoin: true }
+    }
+
+    // the greeting for nick, if the channel has one
+    pub fn greeting_for(&self, nick: &str) -> Option<String> {
+        self.greeting.as_ref().map(|greeting| greeting.replace("{nick}", nick))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ChannelEntry {
+    Name(String),
+    Full {
+        name: String,
+        #[serde(default)]
+        key: Option<String>,
+        #[serde(default = "enabled")]
+        log: bool,
+        #[serde(default)]
+        greeting: Option<String>,
+        #[serde(default = "enabled")]
+        auto_rejoin: bool,
+    },
+}
+
+fn enabled() -> bool {
+    true
+}
+
+impl From<ChannelEntry> for ChannelConfig {
+    fn from(entry: ChannelEntry) -> Self {
+        match entry {
+            ChannelEntry::Name(name) => ChannelConfig::new(&name),
+            ChannelEntry::Full { name, key, log, greeting, auto_rejoin } => ChannelConfig { name, key, log, greeting, auto_rejoin },
+        }
+    }
+}
+
+// A channel name the server will take: a prefix and no spaces or commas.
+pub fn check_name(name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        return Err("a channel has no name".to_string());
+    }
+    if !is_channel(name) {
+        return Err(format!("channel {:?} should start with #, % (Buzzen), & or +", name));
+    }
+    if let Some(c) = name.chars().find(|c| matches!(c, ' ' | ',' | '\u{0007}')) {
+        return Err(format!("channel {:?} can't contain {:?}", name, c));
+    }
+    Ok(())
+}
+
+// Look a channel up by name, ignoring case.
+pub fn find<'a>(channels: &'a [ChannelConfig], name: &str) -> Option<&'a ChannelConfig> {
+    channels.iter().find(|channel| channel.name.eq_ignore_ascii_case(name))
+}
+
+// As few JOIN lines as possible for (channel, key) pairs:
+//   JOIN #keyed,#other,#open key1,key2
+// Channels with a key go first, since keys are matched up by position.
+pub fn join_lines(channels: &[(String, Option<String>)]) -> Vec<String> {
+    let mut sorted: Vec<&(String, Option<String>)> = channels.iter().filter(|(name, _)| !name.is_empty()).collect();
+    sorted.sort_by_key(|(_, key)| key.is_none());
+
+    let mut lines = Vec::new();
+    let mut names: Vec<&str> = Vec::new();
+    let mut keys: Vec<&str> = Vec::new();
+    for (name, key) in sorted {
+        let added = name.len() + 1 + key.as_ref().map_or(0, |key| key.len() + 1);
+        if !names.is_empty() && join_length(&names, &keys) + added > MAX_JOIN_LENGTH {
+            lines.push(join_line(&names, &keys));
+            names.clear();
+            keys.clear();
+        }
+        names.push(name);
+        if let Some(key) = key {
+            keys.push(key);
+        }
+    }
+    if !names.is_empty() {
+        lines.push(join_line(&names, &keys));
+    }
+    lines
+}
+
+fn join_line(names: &[&str], keys: &[&str]) -> String {
+    if keys.is_empty() {
+        format!("JOIN {}", names.join(","))
+    } else {
+        format!("JOIN {} {}", names.join(","), keys.join(","))
+    }
+}
+
+fn join_length(names: &[&str], keys: &[&str]) -> usize {
+    join_line(names, keys).len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channel(name: &str, key: Option<&str>) -> (String, Option<String>) {
+        (name.to_string(), key.map(str::to_string))
+    }
+
+    #[test]
+    fn keyed_channels_go_first() {
+        let lines = join_lines(&[channel("#open", None), channel("#keyed", Some("k1")), channel("#other", None), channel("#also", Some("k2"))]);
+        assert_eq!(lines, vec!["JOIN #keyed,#also,#open,#other k1,k2"]);
+    }
+
+    #[test]
+    fn no_keys_no_key_list() {
+        assert_eq!(join_lines(&[channel("#a", None), channel("", None), channel("#b", None)]), vec!["JOIN #a,#b"]);
+        assert!(join_lines(&[]).is_empty());
+    }
+
+    #[test]
+    fn long_lists_are_split_under_the_limit() {
+        let channels: Vec<_> = (0..100).map(|i| channel(&format!("#channel-number-{:03}", i), (i % 3 == 0).then_some("secret"))).collect();
+        let lines = join_lines(&channels);
+        assert!(lines.len() > 1);
+        let mut joined = 0;
+        for line in &lines {
+            assert!(line.len() <= MAX_JOIN_LENGTH, "{} bytes: {}", line.len(), line);
+            let mut parts = line.split(' ').skip(1);
+            let names = parts.next().unwrap().split(',').count();
+            // every key still lines up with a channel on the same line
+            let keys = parts.next().map_or(0, |keys| keys.split(',').count());
+            assert!(keys <= names);
+            joined += names;
+        }
+        assert_eq!(joined, 100);
+    }
+
+    #[test]
+    fn config_entries_as_names_or_objects() {
+        let channels: Vec<ChannelConfig> =
+            serde_json::from_str(r##"["%#Lobby", {"name": "#secret", "key": "hunter2", "log": false, "greeting": "Hi {nick}!"}]"##).unwrap();
+        assert_eq!(channels[0], ChannelConfig::new("%#Lobby"));
+        assert_eq!(channels[1].key.as_deref(), Some("hunter2"));
+        assert!(!channels[1].log);
+        assert!(channels[1].auto_rejoin);
+        assert_eq!(channels[1].greeting_for("bob").as_deref(), Some("Hi bob!"));
+        assert!(find(&channels, "#SECRET").is_some());
+    }
+
+    #[test]
+    fn names_are_checked() {
+        assert!(check_name("#rust").is_ok());
+        assert!(check_name("%#Lobby").is_ok());
+        // what config accepts, the terminal's /part and /kick see as a channel too
+        for name in ["&local", "+modeless"] {
+            assert!(check_name(name).is_ok());
+            assert!(is_channel(name));
+        }
+        assert!(check_name("").is_err());
+        assert!(check_name("rust").is_err());
+        assert!(check_name("#a,#b").is_err());
+        assert!(check_name("#a b").is_err());
+    }
+}
@@ -0,0 +1,630 @@
+use std::io;
+use std::sync::{atomic::{AtomicBool, Ordering}, Arc};
+use std::time::Duration;
+use tokio::{io::{AsyncReadExt, ReadHalf}, sync::{broadcast, mpsc, watch, Mutex, Notify}, task::JoinHandle};
+
+use crate::caps::{CapNegotiator, Caps};
+use crate::channels::{self, ChannelConfig};
+use crate::codec::{self, Decoding, DecodedLine, LineBuffer};
+use crate::config::BuzzenConfig;
+use crate::event::{Envelope, Event};
+use crate::handler::{Context, Dispatcher, EventHandler};
+use crate::keepalive::{Keepalive, Tick};
+use crate::login::LoginStrategy;
+use crate::message::{IrcMessage, Prefix};
+use crate::reconnect::Backoff;
+use crate::reload::{ConfigDiff, Reloader};
+use crate::sasl::SaslSession;
+use crate::sender::{Outbound, Sender};
+use crate::servers::ServerConfig;
+use crate::tags::Tags;
+use crate::transport::{self, Transport};
+use crate::writer;
+
+// One IRC client. Handlers, subscribers and Senders stay valid across
+// reconnects; only the TCP connection underneath is replaced.
+pub struct IrcClient {
+    config: BuzzenConfig,
+    login: Arc<dyn LoginStrategy>,
+    caps: CapNegotiator,
+    // the SASL exchange in progress, if any
+    sasl: Option<SaslSession>,
+    connection: Option<Connection>,
+    buffer: LineBuffer,
+    keepalive: Keepalive,
+    dispatcher: Dispatcher,
+    outbound: Arc<Mutex<Outbound>>,
+    quit: Arc<AtomicBool>,
+    // a QUIT was queued, see wait_to_reconnect
+    quitting: Arc<Notify>,
+    // opens the writer's queue on 001, see writer.rs
+    registered: Arc<watch::Sender<bool>>,
+    // delays between reconnects, reset once a server has welcomed us (001)
+    backoff: Backoff,
+    nickname: String,
+    address: String,
+    // channels we are in, rejoined after a reconnect
+    channels: Vec<String>,
+    reloader: Reloader,
+    reloads: mpsc::UnboundedReceiver<BuzzenConfig>,
+    // a reloaded config needs a new connection, skip the backoff
+    reconnect_now: bool,
+    // where in config.server_list() the next connection goes
+    server_index: usize,
+}
+
+struct Connection {
+    reader: ReadHalf<Box<dyn Transport>>,
+    writer: JoinHandle<io::Result<()>>,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.writer.abort();
+    }
+}
+
+impl IrcClient {
+    // Set up the client without connecting yet, so handlers can be added first.
+    pub fn new(config: &BuzzenConfig) -> io::Result<Self> {
+        let mut buffer = LineBuffer::new();
+        if let Some(label) = &config.encoding {
+            let encoding = codec::encoding_for_label(label)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("Unknown encoding: {}", label)))?;
+            buffer.set_fallback(Some(encoding));
+        }
+
+        let (sender, outbound) = Sender::new();
+        sender.set_server(&config.server_list().first().map(ServerConfig::address).unwrap_or_default());
+        let quit = outbound.quit.clone();
+        let quitting = outbound.quitting.clone();
+        let registered = outbound.registered.clone();
+        let caps = Caps::default();
+        let (reloader, reloads) = Reloader::new();
+        Ok(IrcClient {
+            config: config.clone(),
+            login: config.network.login(),
+            caps: CapNegotiator::new(config.caps.clone(), config.sasl.is_some(), caps.clone()),
+            sasl: None,
+            connection: None,
+            buffer,
+            keepalive: Keepalive::new(config.keepalive.clone()),
+            dispatcher: Dispatcher::new(Context { network: config.network_name().to_string(), sender, caps, tags: Tags::new() }),
+            outbound: Arc::new(Mutex::new(outbound)),
+            quit,
+            quitting,
+            registered,
+            backoff: Backoff::new(config.reconnect.clone()),
+            nickname: String::new(),
+            address: String::new(),
+            channels: Vec::new(),
+            reloader,
+            reloads,
+            reconnect_now: false,
+            server_index: 0,
+        })
+    }
+
+    // Handlers are called in the order they were added, for every event.
+    pub fn add_handler<H: EventHandler + 'static>(&mut self, handler: H) {
+        self.dispatcher.add_handler(Arc::new(handler));
+    }
+
+    // Log in some other way than the "network" in the config says.
+    pub fn set_login<L: LoginStrategy + 'static>(&mut self, login: L) {
+        self.login = Arc::new(login);
+    }
+
+    // Handle for queueing lines to the server from outside the client.
+    pub fn sender(&self) -> Sender {
+        self.dispatcher.context().sender.clone()
+    }
+
+    // Every event from now on; subscribe before connecting so nothing is missed.
+    pub fn subscribe(&self) -> broadcast::Receiver<Envelope> {
+        self.dispatcher.subscribe()
+    }
+
+    // Handle for giving the running client a new config, see reload.rs.
+    pub fn reloader(&self) -> Reloader {
+        self.reloader.clone()
+    }
+
+    // Queue a line for the writer task.
+    pub fn write(&self, data: &str) -> io::Result<()> {
+        self.dispatcher.context().sender.send(data)
+    }
+
+    // the client's own lines, which don't wait for 001
+    fn send_own(&self, line: &str) -> io::Result<()> {
+        self.dispatcher.context().sender.send_own(line)
+    }
+
+    // Round trip of the last keepalive PING, None until the first PONG. Also
+    // on the Sender, for while run() has the client.
+    pub fn lag(&self) -> Option<Duration> {
+        self.dispatcher.context().sender.lag()
+    }
+
+    // The server we are connected to, or were last, as "host:port". Also on
+    // the Sender, for while run() has the client.
+    pub fn server(&self) -> String {
+        self.dispatcher.context().sender.server()
+    }
+
+    // Open a new connection (dropping any old one) to the current server in
+    // the list and log in.
+    pub async fn connect(&mut self) -> io::Result<()> {
+        self.connection = None;
+        let server = self.next_up().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No server configured"))?;
+        let address = server.address();
+        self.dispatcher.context().sender.set_server(&address);
+
+        let timeout = Duration::from_secs_f64(self.config.reconnect.connect_timeout_secs.max(0.001));
+        let tls = server.tls_config(&self.config.tls);
+        let connecting = transport::connect(&address, &tls, self.config.proxy.as_ref());
+        let stream = tokio::time::timeout(timeout, connecting)
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, format!("Timed out connecting to {}", address)))??;
+        self.attach(stream).await
+    }
+
+    // the server the next connection goes to
+    fn next_up(&self) -> Option<ServerConfig> {
+        let servers = self.config.server_list();
+        let index = self.server_index % servers.len().max(1);
+        servers.into_iter().nth(index)
+    }
+
+    // Move on to the next server in the list, after the last one comes the first.
+    fn next_server(&mut self) {
+        let count = self.config.server_list().len().max(1);
+        self.server_index = (self.server_index + 1) % count;
+    }
+
+    // Log in over a stream that is already open, TLS, plain TCP or anything
+    // else that reads and writes.
+    pub async fn attach<T: Transport>(&mut self, stream: T) -> io::Result<()> {
+        self.connection = None;
+        self.buffer.clear();
+        self.keepalive.reset();
+        self.dispatcher.context().sender.set_lag(None);
+        self.registered.send_replace(false);
+        self.sasl = None;
+
+        let stream: Box<dyn Transport> = Box::new(stream);
+        let (reader, mut write_half) = tokio::io::split(stream);
+        self.dispatcher.emit(Event::Connected { server: self.server() }).await;
+
+        // the handshake goes out before anything still queued from the last connection
+        // CAP LS goes first so the server holds registration until CAP END
+        let mut handshake: Vec<String> = self.caps.start().into_iter().collect();
+        handshake.extend(self.login.handshake(&self.config));
+        writer::write_now(&mut write_half, &handshake, &self.dispatcher).await?;
+
+        let writer = tokio::spawn(writer::run(write_half, self.outbound.clone(), self.config.rate_limit.clone(), self.dispatcher.clone()));
+        self.connection = Some(Connection { reader, writer });
+        Ok(())
+    }
+
+    // Connect and keep the connection up: when it drops, reconnect with
+    // backoff to the next server in the list and rejoin every channel we
+    // were in. Returns after /quit, or once the reconnect settings say to
+    // give up.
+    pub async fn run(&mut self) -> io::Result<()> {
+        loop {
+            if self.connection.is_none() {
+                if let Err(err) = self.connect().await {
+                    self.dispatcher.emit(Event::Disconnected { reason: err.to_string() }).await;
+                    if !self.config.reconnect.enabled {
+                        return Err(err);
+                    }
+                    self.next_server();
+                    self.wait_to_reconnect().await?;
+                    if self.quit.load(Ordering::Relaxed) {
+                        return Ok(());
+                    }
+                    continue;
+                }
+            }
+
+            let err = match self.process_messages().await {
+                Ok(()) => io::Error::new(io::ErrorKind::UnexpectedEof, "Connection closed"),
+                Err(err) => err,
+            };
+            self.connection = None;
+            self.dispatcher.emit(Event::Disconnected { reason: err.to_string() }).await;
+
+            if self.quit.load(Ordering::Relaxed) {
+                return Ok(());
+            }
+            if std::mem::take(&mut self.reconnect_now) {
+                self.backoff.reset();
+                continue;
+            }
+            if !self.config.reconnect.enabled {
+                return Ok(());
+            }
+            self.next_server();
+            self.wait_to_reconnect().await?;
+            if self.quit.load(Ordering::Relaxed) {
+                return Ok(());
+            }
+        }
+    }
+
+    // Sleep until the next attempt. The backoff keeps growing until a server
+    // lets us log in, so one that accepts and then drops us isn't hammered.
+    async fn wait_to_reconnect(&mut self) -> io::Result<()> {
+        match self.backoff.next_delay() {
+            Some(delay) => {
+                let server = self.next_up().map(|server| server.address()).unwrap_or_default();
+                self.dispatcher.emit(Event::Reconnecting { server, attempt: self.backoff.attempt(), delay_secs: delay.as_secs_f64() }).await;
+                let quitting = self.quitting.clone();
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {},
+                    // /quit while we are down: there is no writer to send it, just stop
+                    _ = quitting.notified() => {
+                        self.quit.store(true, Ordering::Relaxed);
+                    },
+                    // a new config is worth trying right away
+                    Some(config) = self.reloads.recv() => {
+                        self.reload(config, false).await?;
+                    },
+                }
+                Ok(())
+            }
+            None => Err(io::Error::new(io::ErrorKind::TimedOut, "Giving up on reconnecting")),
+        }
+    }
+
+    // Take on a new config, changing what can be changed over the current
+    // connection. Returns true when it takes a new connection instead.
+    async fn reload(&mut self, config: BuzzenConfig, connected: bool) -> io::Result<bool> {
+        let diff = ConfigDiff::between(&self.config, &config);
+        // so they aren't rejoined after the next reconnect
+        self.channels.retain(|joined| !diff.part.iter().any(|part| part.eq_ignore_ascii_case(joined)));
+
+        self.buffer.set_fallback(config.encoding.as_deref().and_then(codec::encoding_for_label));
+        self.keepalive.set_config(config.keepalive.clone());
+        self.backoff.set_config(config.reconnect.clone());
+        // a custom login from set_login stays unless the network changes
+        if config.network != self.config.network {
+            self.login = config.network.login();
+        }
+
+        if config.server_list() != self.config.server_list() {
+            // start over at the top of the new list
+            self.server_index = 0;
+        }
+        // used from the next connection on, even when this one carries on
+        self.caps.set_wanted(config.caps.clone(), config.sasl.is_some());
+        if !diff.reconnect && connected {
+            if let Some(nick) = &diff.nick {
+                self.send_own(&format!("NICK {}", nick))?;
+            }
+            for channel in &diff.part {
+                self.send_own(&format!("PART {}", channel))?;
+            }
+            let joins: Vec<(String, Option<String>)> = diff.join.iter().map(|channel| (channel.name.clone(), channel.key.clone())).collect();
+            for line in channels::join_lines(&joins) {
+                self.send_own(&line)?;
+            }
+        }
+
+        self.config = config;
+        self.dispatcher.reload(&self.config);
+        if !diff.is_empty() {
+            self.dispatcher.emit(Event::ConfigReloaded { changes: diff.changes }).await;
+        }
+        Ok(diff.reconnect)
+    }
+
+    // The server ACKed "sasl": authenticate if it takes our mechanism, or
+    // carry on without.
+    async fn start_sasl(&mut self) -> io::Result<()> {
+        let Some(sasl) = &self.config.sasl else {
+            return Ok(());
+        };
+        // "sasl=PLAIN,EXTERNAL" with cap-notify, just "sasl" on older servers
+        let offered = self.dispatcher.context().caps.value("sasl").unwrap_or_default();
+        if !offered.is_empty() && !offered.split(',').any(|mechanism| mechanism == sasl.mechanism.name()) {
+            let reason = format!("Server does not support {}, only {}", sasl.mechanism.name(), offered);
+            self.dispatcher.emit(Event::SaslFailed { reason }).await;
+            if let Some(line) = self.caps.release() {
+                self.send_own(&line)?;
+            }
+            return Ok(());
+        }
+
+        let username = sasl.username.as_deref().unwrap_or(&self.config.nickname);
+        let password = sasl.password.as_deref().unwrap_or(&self.config.password);
+        let session = SaslSession::new(sasl.mechanism, username, password);
+        self.send_own(&session.start())?;
+        self.sasl = Some(session);
+        Ok(())
+    }
+
+    fn finish_sasl(&mut self) -> io::Result<()> {
+        self.sasl = None;
+        if let Some(line) = self.caps.release() {
+            self.send_own(&line)?;
+        }
+        Ok(())
+    }
+
+    pub async fn process_messages(&mut self) -> io::Result<()> {
+        let mut buffer = [0; 4096];
+        loop {
+            let connection = self
+                .connection
+                .as_mut()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "Not connected"))?;
+            let deadline = self.keepalive.deadline();
+            tokio::select! {
+                bytes_read = connection.reader.read(&mut buffer) => {
+                    let bytes_read = bytes_read?;
+                    if bytes_read == 0 {
+                        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Connection closed"));
+                    }
+                    self.buffer.extend(&buffer[..bytes_read]);
+                    self.keepalive.activity();
+                },
+                _ = async {
+                    match deadline {
+                        Some(deadline) => tokio::time::sleep_until(deadline).await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    match self.keepalive.tick() {
+                        Some(Tick::Ping(token)) => self.send_own(&format!("PING :{}", token))?,
+                        Some(Tick::TimedOut(waited)) => {
+                            let reason = format!("Ping timeout: no reply for {} seconds", waited.as_secs());
+                            return Err(io::Error::new(io::ErrorKind::TimedOut, reason));
+                        },
+                        None => {},
+                    }
+                },
+                Some(config) = self.reloads.recv() => {
+                    if self.reload(config, true).await? {
+                        self.reconnect_now = true;
+                        return Err(io::Error::new(io::ErrorKind::ConnectionReset, "Reconnecting with the new config"));
+                    }
+                },
+                // the writer only stops when writing failed
+                result = &mut connection.writer => {
+                    return match result {
+                        Ok(Err(err)) => Err(err),
+                        _ => Err(io::Error::new(io::ErrorKind::BrokenPipe, "Writer stopped")),
+                    };
+                },
+            }
+            while let Some(DecodedLine { text: line, decoding }) = self.buffer.next_line() {
+                self.report_dropped().await;
+                if line.is_empty() {
+                    continue;
+                }
+                if decoding != Decoding::Utf8 {
+                    self.dispatcher.emit(Event::DecodeFallback { line: line.clone(), decoding }).await;
+                }
+                // each line in its unparsed form, the console prints these with --verbose
+                self.dispatcher.emit(Event::Received { line: line.clone() }).await;
+
+                match IrcMessage::parse(&line) {
+                    Ok(msg) => self.dispatch(&line, msg).await?,
+                    Err(err) => self.dispatcher.emit(err.into()).await,
+                }
+            }
+            self.report_dropped().await;
+        }
+    }
+
+    // an oversized line LineBuffer threw away shows up as Malformed
+    async fn report_dropped(&mut self) {
+        if let Some(start) = self.buffer.take_dropped() {
+            let reason = format!("Line longer than {} bytes, dropped", codec::MAX_LINE_LENGTH);
+            self.dispatcher.emit(Event::Malformed { line: start, reason }).await;
+        }
+    }
+
+    // Keep track of our own state, then hand the line to subscribers as an Event.
+    async fn dispatch(&mut self, line: &str, msg: IrcMessage) -> io::Result<()> {
+        let from_me = !self.nickname.is_empty() && msg.nick().eq_ignore_ascii_case(&self.nickname);
+        match msg.command.as_str() {
+            "PING" => {
+                self.send_own(&format!("PONG :{}", msg.text()))?;
+                return Ok(());
+            },
+            "CAP" => {
+                let update = self.caps.handle(&msg);
+                for line in &update.lines {
+                    self.send_own(line)?;
+                }
+                if self.caps.is_holding() && update.added.iter().any(|cap| cap == "sasl") {
+                    self.start_sasl().await?;
+                }
+                if !update.added.is_empty() || !update.removed.is_empty() {
+                    self.dispatcher.emit(Event::CapsChanged { added: update.added, removed: update.removed }).await;
+                }
+                // PASS was left out for SASL, so logging in without it is worth knowing about
+                let ended = update.lines.iter().any(|line| line == "CAP END");
+                if ended && self.config.sasl.is_some() && !self.dispatcher.context().caps.is_enabled("sasl") {
+                    let reason = "Server did not enable the sasl cap, logging in without SASL".to_string();
+                    self.dispatcher.emit(Event::SaslFailed { reason }).await;
+                }
+                return Ok(());
+            },
+            "AUTHENTICATE" => {
+                if let Some(session) = &mut self.sasl {
+                    match session.handle(msg.text()) {
+                        Ok(lines) => {
+                            for line in &lines {
+                                self.send_own(line)?;
+                            }
+                        },
+                        Err(reason) => {
+                            // the server answers with 906 and we carry on from there
+                            self.send_own("AUTHENTICATE *")?;
+                            self.dispatcher.emit(Event::SaslFailed { reason }).await;
+                        },
+                    }
+                }
+                return Ok(());
+            },
+            // SASL succeeded, or we were logged in already
+            "903" | "907" if self.sasl.is_some() => {
+                self.finish_sasl()?;
+            },
+            // SASL failed, aborted or the payload was too long
+            "902" | "904" | "905" | "906" if self.sasl.is_some() => {
+                // after our own abort SaslFailed went out already, with the real reason
+                let ours = msg.command == "906" && self.sasl.as_ref().is_some_and(|session| session.aborted());
+                self.finish_sasl()?;
+                if !ours {
+                    self.dispatcher.emit(Event::SaslFailed { reason: msg.text().to_string() }).await;
+                }
+            },
+            "PONG" => {
+                // the reply to our own keepalive PING isn't worth an event of its own
+                if let Some(lag) = self.keepalive.pong(msg.text()) {
+                    self.dispatcher.context().sender.set_lag(Some(lag));
+                    self.dispatcher.emit(Event::Lag { millis: lag.as_millis() as u64 }).await;
+                    return Ok(());
+                }
+            },
+            "NICK" if from_me => {
+                // keep track of your own nick change
+                self.nickname = msg.text().to_string();
+            },
+            "JOIN" if from_me => {
+                let channel = msg.text();
+                if !self.channels.iter().any(|joined| joined.eq_ignore_ascii_case(channel)) {
+                    self.channels.push(channel.to_string());
+                }
+            },
+            "JOIN" => {
+                let channel_list = self.config.channel_list();
+                if let Some(greeting) = channels::find(&channel_list, msg.text()).and_then(|channel| channel.greeting_for(msg.nick())) {
+                    self.send_own(&format!("PRIVMSG {} :{}", msg.text(), greeting))?;
+                }
+            },
+            "PART" if from_me => {
+                let channel = msg.param(0).unwrap_or(msg.text());
+                self.channels.retain(|joined| !joined.eq_ignore_ascii_case(channel));
+            },
+            "KICK" if msg.params[1].eq_ignore_ascii_case(&self.nickname) => {
+                self.channels.retain(|joined| !joined.eq_ignore_ascii_case(&msg.params[0]));
+                let channel_list = self.config.channel_list();
+                if let Some(channel) = channels::find(&channel_list, &msg.params[0]).filter(|channel| channel.auto_rejoin) {
+                    for line in channels::join_lines(&[(channel.name.clone(), channel.key.clone())]) {
+                        self.send_own(&line)?;
+                    }
+                }
+            },
+            "001" => {
+                /* Welcome to...  */
+                // a server without CAP support never answered CAP LS
+                if self.caps.is_negotiating() && self.config.sasl.is_some() {
+                    let reason = "Server does not support CAP, logged in without SASL".to_string();
+                    self.dispatcher.emit(Event::SaslFailed { reason }).await;
+                }
+                // logged in, so the next drop starts over with a short delay
+                self.backoff.reset();
+                // and the server takes what was queued in the meantime
+                self.registered.send_replace(true);
+                if let Some(nick) = msg.param(0) {
+                    self.nickname = nick.to_string();
+                }
+                // most servers also put our full mask in the text
+                if let Some(mask) = msg.text().split(' ').find(|word| word.contains('!') && word.contains('@')) {
+                    let prefix = Prefix::parse(mask);
+                    self.nickname = prefix.nick.clone();
+                    self.address = prefix.address();
+                }
+            },
+            _ => {}
+        }
+
+        self.dispatcher.emit_tagged(Event::from_message(line, &msg), msg.tags.clone()).await;
+
+        if msg.command == "001" {
+            // the configured channels, then whatever else we were in before a reconnect
+            let mut channel_list = self.config.channel_list();
+            for channel in &self.channels {
+                if channels::find(&channel_list, channel).is_none() {
+                    channel_list.push(ChannelConfig::new(channel));
+                }
+            }
+            let joins: Vec<(String, Option<String>)> = channel_list.into_iter().map(|channel| (channel.name, channel.key)).collect();
+            for line in channels::join_lines(&joins) {
+                self.send_own(&line)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock;
+
+    #[tokio::test]
+    async fn reloaded_caps_are_requested_on_the_next_connection() {
+        let config = BuzzenConfig { caps: vec!["server-time".to_string()], ..mock::config() };
+        let mut client = IrcClient::new(&config).unwrap();
+        let reloaded = BuzzenConfig { caps: vec!["message-tags".to_string()], ..config };
+        // a caps change alone doesn't reconnect
+        assert!(!client.reload(reloaded, true).await.unwrap());
+
+        let (mut server, _client) = mock::connect(client).await;
+        server.expect("CAP LS 302").await;
+        server.expect("NICK bot").await;
+        server.expect("USER bot 0 * :RustBot").await;
+        server.send(":srv CAP * LS :server-time message-tags").await;
+        server.expect("CAP REQ :message-tags").await;
+    }
+
+    #[tokio::test]
+    async fn queued_lines_wait_for_the_welcome() {
+        let client = IrcClient::new(&mock::config()).unwrap();
+        let sender = client.sender();
+        sender.privmsg("#a", "typed while down").unwrap();
+
+        let (mut server, _client) = mock::connect(client).await;
+        server.expect("NICK bot").await;
+        server.expect("USER bot 0 * :RustBot").await;
+        // still registering: the client answers, the queued line waits
+        server.send("PING :srv").await;
+        server.expect("PONG :srv").await;
+        server.send(":srv 001 bot :Welcome").await;
+        server.expect("PRIVMSG #a :typed while down").await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn keepalive_ping_measures_lag() {
+        let mut config = mock::config();
+        config.keepalive.idle_secs = 30.0;
+        let client = IrcClient::new(&config).unwrap();
+        let sender = client.sender();
+        let (mut server, _client) = mock::connect(client).await;
+        server.expect("NICK bot").await;
+        server.expect("USER bot 0 * :RustBot").await;
+        server.expect("PING :rustyirc-1").await;
+        tokio::time::advance(Duration::from_millis(40)).await;
+        server.send(":srv PONG srv :rustyirc-1").await;
+        while sender.lag().is_none() {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(sender.lag(), Some(Duration::from_millis(40)));
+    }
+
+    #[tokio::test]
+    async fn sender_reports_the_next_server() {
+        let config = BuzzenConfig { server: "irc.example.net:6667".to_string(), ..mock::config() };
+        let client = IrcClient::new(&config).unwrap();
+        let sender = client.sender();
+        assert_eq!(sender.server(), "irc.example.net:6667");
+        assert_eq!(sender.lag(), None);
+    }
+}
@@ -0,0 +1,226 @@
+// Turns the raw bytes coming off the socket into lines of text.
+//
+// Lines are framed on LF (a preceding CR is dropped) before anything is
+// decoded, so a multibyte character split across two reads is put back
+// together first. Each line is then decoded on its own: UTF-8 when it is
+// valid, otherwise the configured fallback encoding (Buzzen users often send
+// CP1252/Latin-1), otherwise UTF-8 with replacement characters.
+//
+// A line longer than IRCv3 allows (8191 bytes of tags plus 512 for the rest)
+// is not kept: it is skipped up to its LF, so a server that never sends one
+// can't grow the buffer forever.
+
+use encoding_rs::Encoding;
+use serde::Serialize;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Decoding {
+    Utf8,
+    // the line was not valid UTF-8 and was decoded with this encoding
+    Fallback(&'static str),
+    // the line was not valid UTF-8 and invalid sequences were replaced with U+FFFD
+    Lossy,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedLine {
+    pub text: String,
+    pub decoding: Decoding,
+}
+
+pub const MAX_LINE_LENGTH: usize = 8191 + 512;
+
+pub struct LineBuffer {
+    buffer: Vec<u8>,
+    fallback: Option<&'static Encoding>,
+    // the rest of an oversized line is thrown away up to its LF
+    skipping: bool,
+    // how the last oversized line started, until take_dropped
+    dropped: Option<String>,
+}
+
+impl LineBuffer {
+    pub fn new() -> Self {
+        LineBuffer { buffer: Vec::new(), fallback: None, skipping: false, dropped: None }
+    }
+
+    pub fn set_fallback(&mut self, fallback: Option<&'static Encoding>) {
+        self.fallback = fallback;
+    }
+
+    // drop a partial line, e.g. left over from a closed connection
+    pub fn clear(&mut self) {
+        self.buffer.clear();
+        self.skipping = false;
+    }
+
+    pub fn extend(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    // next complete line, or None until the rest of it has been read
+    pub fn next_line(&mut self) -> Option<DecodedLine> {
+        let pos = loop {
+            let Some(pos) = self.buffer.iter().position(|&byte| byte == b'\n') else {
+                if self.skipping {
+                    self.buffer.clear();
+                } else if self.buffer.len() > MAX_LINE_LENGTH {
+                    self.drop_line(self.buffer.len());
+                    self.skipping = true;
+                }
+                return None;
+            };
+            if self.skipping {
+                self.buffer.drain(..=pos);
+                self.skipping = false;
+            } else if pos + 1 > MAX_LINE_LENGTH {
+                self.drop_line(pos + 1);
+            } else {
+                break pos;
+            }
+        };
+        let mut line: Vec<u8> = self.buffer.drain(..=pos).collect();
+        line.pop();
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        Some(decode(&line, self.fallback))
+    }
+
+    // The start of a line that was too long and dropped since the last call.
+    pub fn take_dropped(&mut self) -> Option<String> {
+        self.dropped.take()
+    }
+
+    fn drop_line(&mut self, len: usize) {
+        let start = &self.buffer[..len.min(80)];
+        self.dropped = Some(decode(start, self.fallback).text);
+        self.buffer.drain(..len);
+    }
+}
+
+impl Default for LineBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn decode(bytes: &[u8], fallback: Option<&'static Encoding>) -> DecodedLine {
+    if let Ok(text) = std::str::from_utf8(bytes) {
+        return DecodedLine { text: text.to_string(), decoding: Decoding::Utf8 };
+    }
+    match fallback {
+        Some(encoding) => {
+            let (text, had_errors) = encoding.decode_without_bom_handling(bytes);
+            let decoding = if had_errors { Decoding::Lossy } else { Decoding::Fallback(encoding.name()) };
+            DecodedLine { text: text.into_owned(), decoding }
+        }
+        None => DecodedLine { text: String::from_utf8_lossy(bytes).into_owned(), decoding: Decoding::Lossy },
+    }
+}
+
+// "cp1252", "latin1", "iso-8859-1", ... (WHATWG labels)
+pub fn encoding_for_label(label: &str) -> Option<&'static Encoding> {
+    Encoding::for_label(label.trim().as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf8(text: &str) -> DecodedLine {
+        DecodedLine { text: text.to_string(), decoding: Decoding::Utf8 }
+    }
+
+    #[test]
+    fn multibyte_character_split_across_reads() {
+        let mut buffer = LineBuffer::new();
+        let bytes = "PRIVMSG #a :héllo\r\n".as_bytes();
+        let split = bytes.iter().position(|&byte| byte == 0xc3).unwrap() + 1;
+        buffer.extend(&bytes[..split]);
+        assert_eq!(buffer.next_line(), None);
+        buffer.extend(&bytes[split..]);
+        assert_eq!(buffer.next_line(), Some(utf8("PRIVMSG #a :héllo")));
+        assert_eq!(buffer.next_line(), None);
+    }
+
+    #[test]
+    fn crlf_and_lf_endings() {
+        let mut buffer = LineBuffer::new();
+        buffer.extend(b"one\r\ntwo\nthree\r");
+        assert_eq!(buffer.next_line(), Some(utf8("one")));
+        assert_eq!(buffer.next_line(), Some(utf8("two")));
+        // the CR waits for its LF
+        assert_eq!(buffer.next_line(), None);
+        buffer.extend(b"\n");
+        assert_eq!(buffer.next_line(), Some(utf8("three")));
+    }
+
+    #[test]
+    fn empty_lines_come_through_empty() {
+        let mut buffer = LineBuffer::new();
+        buffer.extend(b"\r\n\n");
+        assert_eq!(buffer.next_line(), Some(utf8("")));
+        assert_eq!(buffer.next_line(), Some(utf8("")));
+    }
+
+    #[test]
+    fn clear_drops_a_partial_line() {
+        let mut buffer = LineBuffer::new();
+        buffer.extend(b"half a li");
+        buffer.clear();
+        buffer.extend(b"whole\n");
+        assert_eq!(buffer.next_line(), Some(utf8("whole")));
+    }
+
+    #[test]
+    fn oversized_lines_are_dropped() {
+        let mut buffer = LineBuffer::new();
+        let long = format!("PRIVMSG #a :{}", "x".repeat(MAX_LINE_LENGTH));
+        buffer.extend(format!("{}\r\nnext\r\n", long).as_bytes());
+        assert_eq!(buffer.next_line(), Some(utf8("next")));
+        assert_eq!(buffer.take_dropped(), Some(long[..80].to_string()));
+        assert_eq!(buffer.take_dropped(), None);
+
+        // without an LF in sight the buffer stops growing
+        for _ in 0..10 {
+            buffer.extend(&[b'y'; 4096]);
+            assert_eq!(buffer.next_line(), None);
+            assert!(buffer.buffer.len() <= MAX_LINE_LENGTH);
+        }
+        assert!(buffer.take_dropped().is_some_and(|start| start.starts_with("yyy")));
+        buffer.extend(b"yyy\nafter\n");
+        assert_eq!(buffer.next_line(), Some(utf8("after")));
+        assert_eq!(buffer.take_dropped(), None);
+    }
+
+    #[test]
+    fn cp1252_fallback() {
+        let mut buffer = LineBuffer::new();
+        buffer.set_fallback(encoding_for_label("cp1252"));
+        // "café €5" in CP1252
+        buffer.extend(b"caf\xe9 \x805\n");
+        assert_eq!(
+            buffer.next_line(),
+            Some(DecodedLine { text: "café €5".to_string(), decoding: Decoding::Fallback("windows-1252") })
+        );
+        // valid UTF-8 is still read as UTF-8
+        buffer.extend("café\n".as_bytes());
+        assert_eq!(buffer.next_line(), Some(utf8("café")));
+    }
+
+    #[test]
+    fn lossy_without_fallback() {
+        let line = decode(b"caf\xe9", None);
+        assert_eq!(line.text, "caf\u{fffd}");
+        assert_eq!(line.decoding, Decoding::Lossy);
+    }
+
+    #[test]
+    fn encoding_labels() {
+        assert_eq!(encoding_for_label(" latin1 ").map(|encoding| encoding.name()), Some("windows-1252"));
+        assert_eq!(encoding_for_label("ISO-8859-2").map(|encoding| encoding.name()), Some("ISO-8859-2"));
+        assert!(encoding_for_label("klingon").is_none());
+    }
+}
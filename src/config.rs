@@ -0,0 +1,424 @@
+use serde::{Deserialize, Serialize};
+use std::{env, error::Error, fmt, fs};
+
+use crate::channels::{self, ChannelConfig};
+use crate::codec;
+use crate::keepalive::KeepaliveConfig;
+use crate::login::Network;
+use crate::proxy::ProxyConfig;
+use crate::ratelimit::RateLimit;
+use crate::reconnect::ReconnectConfig;
+use crate::sasl::{SaslConfig, SaslMechanism};
+use crate::secrets;
+use crate::servers::ServerConfig;
+use crate::transport::{self, TlsConfig};
+
+// Longest delay or timeout the config may ask for, a day. Much bigger ones
+// overflow Duration and Instant further down.
+const MAX_SECS: f64 = 86_400.0;
+
+// Why the config could not be used.
+#[derive(Debug)]
+pub enum ConfigError {
+    // there was no config file, so a template was written to fill in
+    Created { path: String },
+    Unreadable { path: String, reason: String },
+    // everything that is wrong with it, not just the first thing
+    Invalid { path: String, problems: Vec<String> },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Created { path } => {
+                write!(f, "No config found, wrote a template to {}. Fill in at least server and nickname, then start again.", path)
+            },
+            ConfigError::Unreadable { path, reason } => write!(f, "Could not read {}: {}", path, reason),
+            ConfigError::Invalid { path, problems } => {
+                let plural = if problems.len() == 1 { "" } else { "s" };
+                write!(f, "{} has {} problem{}:", path, problems.len(), plural)?;
+                for problem in problems {
+                    write!(f, "\n  - {}", problem)?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+// The settings for one network. config.json holds one of these, or a list of
+// them under "networks", see Config.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct BuzzenConfig {
+    // tells networks apart in events and the terminal, see network_name()
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub name: String,
+    pub nickname: String,
+    pub email: String,
+    pub password: String,
+    // "host:port", tried before the servers below
+    #[serde(default)]
+    pub server: String,
+    // more servers to fail over to, see servers.rs
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub servers: Vec<ServerConfig>,
+    // md5 hex of the password for Buzzen's LOGINH, instead of the password
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_hash: Option<String>,
+    // credentials kept out of this file, see secrets.rs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secrets_file: Option<String>,
+    // channels to join and their settings, see channels.rs
+    #[serde(default)]
+    pub channels: Vec<ChannelConfig>,
+    // older configs name a single channel here, joined before the list above
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub channel: String,
+    // how to log in: "buzzen", "irc" or "twitch", see login.rs
+    #[serde(default)]
+    pub network: Network,
+    // Twitch oauth token, the password is used when this is missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub access_token: Option<String>,
+    // IRCv3 capabilities to request, e.g. ["server-time", "message-tags"]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub caps: Vec<String>,
+    // authenticate with SASL during registration, see sasl.rs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sasl: Option<SaslConfig>,
+    // see transport.rs
+    #[serde(default)]
+    pub tls: TlsConfig,
+    // connect through a SOCKS5 or HTTP proxy, see proxy.rs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<ProxyConfig>,
+    // used for lines that are not valid UTF-8, e.g. "cp1252" or "latin1"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<String>,
+    // outgoing flood protection, see ratelimit.rs
+    #[serde(default)]
+    pub rate_limit: RateLimit,
+    // what to do when the connection drops, see reconnect.rs
+    #[serde(default)]
+    pub reconnect: ReconnectConfig,
+    // client PINGs and ping timeout, see keepalive.rs
+    #[serde(default)]
+    pub keepalive: KeepaliveConfig,
+}
+
+impl BuzzenConfig {
+    // "channel" and "channels" together
+    pub fn channel_list(&self) -> Vec<ChannelConfig> {
+        let mut list = Vec::new();
+        if !self.channel.is_empty() && !self.channels.iter().any(|channel| channel.name.eq_ignore_ascii_case(&self.channel)) {
+            list.push(ChannelConfig::new(&self.channel));
+        }
+        list.extend(self.channels.iter().cloned());
+        list
+    }
+
+    // "server" and "servers" together, in the order they are tried
+    pub fn server_list(&self) -> Vec<ServerConfig> {
+        let mut list = Vec::new();
+        if !self.server.is_empty() {
+            let server = ServerConfig::parse(&self.server);
+            if !self.servers.contains(&server) {
+                list.push(server);
+            }
+        }
+        list.extend(self.servers.iter().cloned());
+        list
+    }
+
+    // Read a single network from file and check it. Files with several
+    // networks give the first one, see Config for all of them.
+    pub fn from_file(filename: &str) -> Result<Self, ConfigError> {
+        Ok(Config::from_file(filename)?.networks.remove(0))
+    }
+
+    // what events and the terminal call this network: "name", or the login type
+    pub fn network_name(&self) -> &str {
+        if self.name.is_empty() {
+            self.network.as_str()
+        } else {
+            &self.name
+        }
+    }
+
+    // Everything that would keep the client from connecting or logging in,
+    // as messages for the user. Empty when the config is fine.
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if self.server.is_empty() && self.servers.is_empty() {
+            problems.push("server is missing, e.g. \"server\": \"irc.example.net:6667\"".to_string());
+        } else if !self.server.is_empty() {
+            if let Err(err) = transport::split_server(&self.server) {
+                problems.push(err.to_string());
+            }
+        }
+        for server in &self.servers {
+            if let Err(reason) = server.check() {
+                problems.push(reason);
+            }
+        }
+
+        if self.nickname.is_empty() {
+            problems.push("nickname is missing".to_string());
+        } else if let Some(c) = self.nickname.chars().find(|c| " ,*?!@:#%".contains(*c)) {
+            problems.push(format!("nickname {:?} can't contain {:?}", self.nickname, c));
+        }
+
+        if self.network == Network::Buzzen {
+            if self.email.is_empty() {
+                problems.push("email is missing, Buzzen logs in with the account email".to_string());
+            }
+            if self.password.is_empty() && self.password_hash.is_none() {
+                problems.push("password is missing, set it here, in RUSTYIRC_PASSWORD or in a secrets_file".to_string());
+            }
+            if let Some(hash) = &self.password_hash {
+                if hash.len() != 32 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                    problems.push("password_hash should be the 32 character md5 hex digest of the password".to_string());
+                }
+            }
+        }
+        if self.network == Network::Twitch && self.access_token.is_none() && self.password.is_empty() {
+            problems.push("access_token is missing, Twitch logs in with an oauth token".to_string());
+        }
+
+        for channel in self.channel_list() {
+            if let Err(reason) = channels::check_name(&channel.name) {
+                problems.push(reason);
+            }
+        }
+
+        if let Some(label) = &self.encoding {
+            if codec::encoding_for_label(label).is_none() {
+                problems.push(format!("encoding {:?} is not a known encoding", label));
+            }
+        }
+        if self.tls.client_cert.is_some() != self.tls.client_key.is_some() {
+            problems.push("tls.client_cert and tls.client_key go together".to_string());
+        }
+        if let Some(proxy) = &self.proxy {
+            if proxy.host.is_empty() || proxy.port == 0 {
+                problems.push("proxy needs a host and a port".to_string());
+            }
+        }
+        let numbers = [
+            ("reconnect.initial_delay_secs", self.reconnect.initial_delay_secs, MAX_SECS),
+            ("reconnect.max_delay_secs", self.reconnect.max_delay_secs, MAX_SECS),
+            ("reconnect.connect_timeout_secs", self.reconnect.connect_timeout_secs, MAX_SECS),
+            ("keepalive.idle_secs", self.keepalive.idle_secs, MAX_SECS),
+            ("keepalive.timeout_secs", self.keepalive.timeout_secs, MAX_SECS),
+            ("rate_limit.burst", self.rate_limit.burst, 1000.0),
+            ("rate_limit.refill_per_second", self.rate_limit.refill_per_second, 1000.0),
+            ("rate_limit.per_byte_penalty", self.rate_limit.per_byte_penalty, 10.0),
+        ];
+        for (name, value, max) in numbers {
+            // also catches NaN and infinity
+            if !(0.0..=max).contains(&value) {
+                problems.push(format!("{} should be between 0 and {}", name, max));
+            }
+        }
+        // a trickle this slow would have lines wait for days
+        if self.rate_limit.refill_per_second > 0.0 && self.rate_limit.refill_per_second < 0.01 {
+            problems.push("rate_limit.refill_per_second should be 0 (no limit) or at least 0.01".to_string());
+        }
+
+        if let Some(sasl) = &self.sasl {
+            if sasl.mechanism == SaslMechanism::External && (!self.tls.enabled || self.tls.client_cert.is_none()) {
+                problems.push("SASL EXTERNAL needs tls enabled with a client_cert".to_string());
+            }
+        }
+        problems
+    }
+
+    // Write configuration to file
+    pub fn to_file(&self, filename: &str) -> Result<(), Box<dyn Error>> {
+        let json = serde_json::to_string_pretty(self)?;
+        secrets::write_config(filename, &json, secrets::has_credentials(self))?;
+        Ok(())
+    }
+}
+
+// Everything in config.json: either a single network, as it always was, or
+//   {"networks": [{"name": "buzzen", ...}, {"name": "twitch", "network": "twitch", ...}]}
+// with one client per network.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub networks: Vec<BuzzenConfig>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config { networks: vec![BuzzenConfig::default()] }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct NetworkList {
+    networks: Vec<BuzzenConfig>,
+}
+
+impl Config {
+    // Read configuration from file, add secrets and check it, see validate()
+    pub fn from_file(filename: &str) -> Result<Self, ConfigError> {
+        let path = path(filename);
+        match Self::read(filename)? {
+            Some(mut config) => {
+                config.load_secrets()?;
+                config.check(filename)
+            },
+            None => {
+                // File doesn't exist, write an empty one to fill in; nothing to connect to until then
+                Config::default()
+                    .to_file(&path)
+                    .map_err(|err| ConfigError::Unreadable { path: path.clone(), reason: err.to_string() })?;
+                Err(ConfigError::Created { path })
+            },
+        }
+    }
+
+    // The config as it is in the file, unchecked. None when there is no file.
+    pub fn read(filename: &str) -> Result<Option<Self>, ConfigError> {
+        let path = path(filename);
+        let unreadable = |reason: String| ConfigError::Unreadable { path: path.clone(), reason };
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(unreadable(err.to_string())),
+        };
+        // look for "networks" first so errors point into the right shape
+        let value: serde_json::Value = serde_json::from_str(&contents).map_err(|err| unreadable(err.to_string()))?;
+        let networks = if value.get("networks").is_some() {
+            serde_json::from_value::<NetworkList>(value).map(|list| list.networks)
+        } else {
+            serde_json::from_value::<BuzzenConfig>(value).map(|network| vec![network])
+        };
+        networks.map(|networks| Some(Config { networks })).map_err(|err| unreadable(err.to_string()))
+    }
+
+    // Credentials from the environment and the secrets files, see secrets.rs
+    pub fn load_secrets(&mut self) -> Result<(), ConfigError> {
+        // with several networks RUSTYIRC_PASSWORD & co. would be ambiguous
+        let shared_env = self.networks.len() == 1;
+        for network in &mut self.networks {
+            secrets::apply(network, shared_env).map_err(|reason| ConfigError::Unreadable {
+                path: network.secrets_file.clone().unwrap_or_default(),
+                reason,
+            })?;
+        }
+        Ok(())
+    }
+
+    // The config if validate() finds nothing wrong, filename is for the message
+    pub fn check(self, filename: &str) -> Result<Self, ConfigError> {
+        let problems = self.validate();
+        if !problems.is_empty() {
+            return Err(ConfigError::Invalid { path: path(filename), problems });
+        }
+        Ok(self)
+    }
+
+    // BuzzenConfig::validate for every network, plus names that clash
+    pub fn validate(&self) -> Vec<String> {
+        if self.networks.is_empty() {
+            return vec!["networks is empty, there is nothing to connect to".to_string()];
+        }
+        let several = self.networks.len() > 1;
+        let mut problems = Vec::new();
+        for (i, network) in self.networks.iter().enumerate() {
+            let name = network.network_name();
+            if several && self.networks[..i].iter().any(|other| other.network_name().eq_ignore_ascii_case(name)) {
+                problems.push(format!("{}: there are two networks called that, give them different \"name\"s", name));
+            }
+            for problem in network.validate() {
+                problems.push(if several { format!("{}: {}", name, problem) } else { problem });
+            }
+        }
+        problems
+    }
+
+    pub fn network(&self, name: &str) -> Option<&BuzzenConfig> {
+        self.networks.iter().find(|network| network.network_name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn network_mut(&mut self, name: &str) -> Option<&mut BuzzenConfig> {
+        self.networks.iter_mut().find(|network| network.network_name().eq_ignore_ascii_case(name))
+    }
+
+    // A single network is written the way it always was, without "networks"
+    pub fn to_file(&self, filename: &str) -> Result<(), Box<dyn Error>> {
+        let json = match self.networks.as_slice() {
+            [network] => serde_json::to_string_pretty(network)?,
+            _ => serde_json::to_string_pretty(&NetworkList { networks: self.networks.clone() })?,
+        };
+        secrets::write_config(filename, &json, self.networks.iter().any(secrets::has_credentials))?;
+        Ok(())
+    }
+}
+
+// filename relative to the current directory, for messages
+fn path(filename: &str) -> String {
+    match env::current_dir() {
+        Ok(dir) => dir.join(filename).display().to_string(),
+        Err(_) => filename.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_server_needs_a_port() {
+        let config = BuzzenConfig { server: "irc.example.net:0".to_string(), nickname: "bot".to_string(), ..Default::default() };
+        assert!(config.validate().contains(&"Server should be host:port, got \"irc.example.net:0\"".to_string()));
+    }
+
+    #[test]
+    fn every_problem_is_listed() {
+        let mut config = BuzzenConfig {
+            server: "irc.example.net".to_string(),
+            servers: vec![ServerConfig::parse("backup.example.net:0")],
+            nickname: "bad nick".to_string(),
+            channels: vec![ChannelConfig::new("lobby")],
+            encoding: Some("klingon".to_string()),
+            password_hash: Some("abc".to_string()),
+            ..Default::default()
+        };
+        config.reconnect.max_delay_secs = -1.0;
+        config.keepalive.timeout_secs = f64::NAN;
+        config.rate_limit.burst = 5000.0;
+        config.rate_limit.refill_per_second = 0.001;
+        assert_eq!(
+            config.validate(),
+            vec![
+                "Server should be host:port, got \"irc.example.net\"",
+                "Server should be host:port, got \"backup.example.net:0\"",
+                "nickname \"bad nick\" can't contain ' '",
+                "email is missing, Buzzen logs in with the account email",
+                "password_hash should be the 32 character md5 hex digest of the password",
+                "channel \"lobby\" should start with #, % (Buzzen), & or +",
+                "encoding \"klingon\" is not a known encoding",
+                "reconnect.max_delay_secs should be between 0 and 86400",
+                "keepalive.timeout_secs should be between 0 and 86400",
+                "rate_limit.burst should be between 0 and 1000",
+                "rate_limit.refill_per_second should be 0 (no limit) or at least 0.01",
+            ]
+        );
+    }
+
+    #[test]
+    fn several_networks_are_named_in_problems() {
+        let network = BuzzenConfig { server: "irc.example.net:6667".to_string(), nickname: "bot".to_string(), network: Network::Irc, ..Default::default() };
+        let config = Config { networks: vec![network.clone(), BuzzenConfig { nickname: String::new(), ..network }] };
+        assert_eq!(
+            config.validate(),
+            vec!["irc: there are two networks called that, give them different \"name\"s", "irc: nickname is missing"]
+        );
+    }
+}
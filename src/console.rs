@@ -0,0 +1,217 @@
+// Terminal output: the default handler, prints every event colored by type,
+// except for channels that have "log" turned off. Verbose also prints every
+// raw line from the server and the keepalive lag. With several networks each
+// line starts with the network's name.
+
+use async_trait::async_trait;
+use std::sync::RwLock;
+
+use crate::channels::ChannelConfig;
+use crate::codec::Decoding;
+use crate::config::BuzzenConfig;
+use crate::event::{is_channel, Event};
+use crate::handler::{self, Context, EventHandler};
+use crate::format::printall;
+
+#[derive(Default)]
+pub struct ConsoleHandler {
+    // channels not to print, updated when the config is reloaded
+    muted: RwLock<Vec<String>>,
+    verbose: bool,
+    // "[name] " in front of every line, empty for none
+    tag: String,
+}
+
+impl ConsoleHandler {
+    pub fn new(channels: &[ChannelConfig]) -> Self {
+        ConsoleHandler { muted: RwLock::new(muted(channels)), verbose: false, tag: String::new() }
+    }
+
+    // builder style: ConsoleHandler::new(&channels).verbose(true)
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    // builder style, name the network on every line
+    pub fn network(mut self, name: &str) -> Self {
+        self.tag = format!("[{}] ", name);
+        self
+    }
+
+    fn print(&self, kind: &str, text: &str) {
+        printall(kind, &format!("{}{}", self.tag, text));
+    }
+}
+
+fn muted(channels: &[ChannelConfig]) -> Vec<String> {
+    channels.iter().filter(|channel| !channel.log).map(|channel| channel.name.clone()).collect()
+}
+
+#[async_trait]
+impl EventHandler for ConsoleHandler {
+    async fn on_event(&self, ctx: &Context, event: &Event) {
+        if let Some(channel) = event.channel() {
+            let muted = self.muted.read().unwrap_or_else(|err| err.into_inner());
+            if muted.iter().any(|muted| muted.eq_ignore_ascii_case(channel)) {
+                return;
+            }
+        }
+        handler::route(self, ctx, event).await
+    }
+
+    fn reload(&self, config: &BuzzenConfig) {
+        *self.muted.write().unwrap_or_else(|err| err.into_inner()) = muted(&config.channel_list());
+    }
+
+    async fn on_welcome(&self, _ctx: &Context, channel: &str, message: &str) {
+        self.print("welcome", &format!(">> Welcome message for {} : {}", channel, message));
+    }
+
+    async fn on_whisper(&self, _ctx: &Context, nick: &str, address: &str, channel: &str, message: &str) {
+        self.print("whisper", &format!(">> Query from {} ({}) in {} : {}", nick, address, channel, message));
+    }
+
+    async fn on_join(&self, _ctx: &Context, nick: &str, address: &str, channel: &str) {
+        self.print("join", &format!(">> Join: {} ({}) has joined {}", nick, address, channel));
+    }
+
+    async fn on_part(&self, _ctx: &Context, nick: &str, address: &str, channel: &str) {
+        self.print("part", &format!(">> Part: {} ({}) has left {}", nick, address, channel));
+    }
+
+    async fn on_quit(&self, _ctx: &Context, nick: &str, address: &str, reason: &str) {
+        self.print("quit", &format!(">> Quit: {} ({}) has left the server. ({})", nick, address, reason));
+    }
+
+    async fn on_nick(&self, _ctx: &Context, nick: &str, address: &str, new_nick: &str) {
+        self.print("nick", &format!(">> Nick: {} ({}) has changed their nick to: {}", nick, address, new_nick));
+    }
+
+    async fn on_privmsg(&self, _ctx: &Context, nick: &str, _address: &str, _channel: &str, message: &str) {
+        self.print("privmsg", &format!("{}: {}", nick, message));
+    }
+
+    async fn on_query(&self, _ctx: &Context, nick: &str, _address: &str, message: &str) {
+        self.print("privmsg", &format!("{}: {}", nick, message));
+    }
+
+    async fn on_action(&self, _ctx: &Context, nick: &str, _address: &str, target: Option<&str>, message: &str) {
+        match target {
+            Some(_) => self.print("action", &format!("{} {}", nick, message)),
+            None => self.print("action", &format!(">> Query from {} : {}", nick, message)),
+        }
+    }
+
+    async fn on_ctcp_request(&self, _ctx: &Context, nick: &str, address: &str, request: &str) {
+        self.print("ctcprequest", &format!(">> CTCP {} Request from {} ({})", request, nick, address));
+    }
+
+    async fn on_ctcp_reply(&self, _ctx: &Context, nick: &str, address: &str, ctcp_type: &str, ctcp_reply: &str) {
+        self.print("ctcpreply", &format!(">> CTCP {} Reply from {} ({}) : {}", ctcp_type, nick, address, ctcp_reply));
+    }
+
+    async fn on_mode(&self, _ctx: &Context, nick: &str, _address: &str, target: &str, modes: &str) {
+        if is_channel(target) {
+            self.print("mode", &format!(">> Mode: {} sets modes in {} to {}", nick, target, modes));
+        } else {
+            self.print("usermode", &format!(">> Usermode: {}", modes));
+        }
+    }
+
+    async fn on_kick(&self, _ctx: &Context, nick: &str, address: &str, knick: &str, channel: &str, reason: &str) {
+        self.print("kick", &format!(">> Kick: {} ({}) has kicked {} from {} : {}", nick, address, knick, channel, reason));
+    }
+
+    async fn on_notice(&self, _ctx: &Context, nick: &str, address: &str, target: Option<&str>, message: &str) {
+        match target {
+            Some(channel) => self.print("notice", &format!(">> Notice to {} from {} ({}): {}", channel, nick, address, message)),
+            None => self.print("notice", &format!(">> Notice from {} ({}): {}", nick, address, message)),
+        }
+    }
+
+    async fn on_server_notice(&self, _ctx: &Context, target: Option<&str>, message: &str) {
+        match target {
+            Some(channel) => self.print("snotice", &format!(">> Notice to {} : {}", channel, message)),
+            None => self.print("snotice", &format!(">> Notice: {}", message)),
+        }
+    }
+
+    async fn on_server_ctcp(&self, _ctx: &Context, ctcp_type: &str, ctcp_reply: &str) {
+        self.print("sctcp", &format!(">> CTCP {} from Server: {}", ctcp_type, ctcp_reply));
+    }
+
+    async fn on_numeric(&self, _ctx: &Context, numeric: &str, message: &str) {
+        // For more information on numerics: https://datatracker.ietf.org/doc/html/rfc2812
+        self.print("numeric", &format!(">> Numeric({}): {}", numeric, message));
+    }
+
+    async fn on_unaway(&self, _ctx: &Context, nick: &str, message: &str) {
+        self.print("unaway", &format!(">> Back: {} has returned! ({})", nick, message));
+    }
+
+    async fn on_away(&self, _ctx: &Context, nick: &str, message: &str) {
+        self.print("away", &format!(">> Away: {} has gone away. ({})", nick, message));
+    }
+
+    async fn on_connected(&self, _ctx: &Context, server: &str) {
+        self.print("alert", &format!("Connected to {}! Starting authentication process...", server));
+    }
+
+    async fn on_disconnected(&self, _ctx: &Context, reason: &str) {
+        self.print("alert", &format!("Disconnected: {}", reason));
+    }
+
+    async fn on_reconnecting(&self, _ctx: &Context, server: &str, attempt: u32, delay_secs: f64) {
+        self.print("alert", &format!("Reconnecting to {} in {:.1}s (attempt {})", server, delay_secs, attempt));
+    }
+
+    async fn on_caps_changed(&self, _ctx: &Context, added: &[String], removed: &[String]) {
+        if !added.is_empty() {
+            self.print("numeric", &format!(">> Capabilities enabled: {}", added.join(" ")));
+        }
+        if !removed.is_empty() {
+            self.print("numeric", &format!(">> Capabilities disabled: {}", removed.join(" ")));
+        }
+    }
+
+    async fn on_sasl_failed(&self, _ctx: &Context, reason: &str) {
+        self.print("alert", &format!("SASL authentication failed: {}", reason));
+    }
+
+    async fn on_config_reloaded(&self, _ctx: &Context, changes: &[String]) {
+        self.print("alert", &format!("Config reloaded: {}", changes.join(", ")));
+    }
+
+    async fn on_lag(&self, _ctx: &Context, millis: u64) {
+        if self.verbose {
+            self.print("numeric", &format!(">> Lag: {}ms", millis));
+        }
+    }
+
+    async fn on_received(&self, _ctx: &Context, line: &str) {
+        if self.verbose {
+            self.print("numeric", &format!(">> {}", line));
+        }
+    }
+
+    async fn on_sent(&self, _ctx: &Context, line: &str) {
+        self.print("default", &format!("<< {}", line));
+    }
+
+    async fn on_decode_fallback(&self, _ctx: &Context, line: &str, decoding: &Decoding) {
+        let text = match decoding {
+            Decoding::Fallback(encoding) => format!("Line was not valid UTF-8, decoded as {}: {}", encoding, line),
+            _ => format!("Line was not valid UTF-8, invalid bytes replaced: {}", line),
+        };
+        self.print("numeric", &text);
+    }
+
+    async fn on_parse_error(&self, _ctx: &Context, line: &str, reason: &str) {
+        self.print("alert", &format!("Malformed line ({}): {}", reason, line));
+    }
+
+    async fn on_unsupported(&self, _ctx: &Context, line: &str) {
+        self.print("default", &format!("Unsupported event: {}", line)); // print anything i have not added/forgot
+    }
+}
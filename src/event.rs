@@ -0,0 +1,294 @@
+// Everything the client reports, as typed values instead of printed text.
+//
+// IrcClient turns each IrcMessage into one Event and broadcasts it to every
+// subscriber (see IrcClient::subscribe) and registered EventHandler.
+// Printing to the terminal is just one of those handlers (console.rs).
+
+use serde::Serialize;
+
+use crate::codec::Decoding;
+use crate::message::{IrcMessage, ParseError};
+use crate::format::{strip_style, trim_trailing_whitespace};
+use crate::tags::Tags;
+
+// What subscribers receive: the event, the network it happened on and the
+// message tags of the line it came from (empty for events the client made up
+// itself).
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Envelope {
+    pub network: String,
+    pub event: Event,
+    pub tags: Tags,
+}
+
+// Serializes as {"type": "privmsg", "nick": ..., ...}
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    Join { nick: String, address: String, channel: String },
+    Part { nick: String, address: String, channel: String },
+    Quit { nick: String, address: String, reason: String },
+    Nick { nick: String, address: String, new_nick: String },
+    Kick { nick: String, address: String, channel: String, target: String, reason: String },
+    Privmsg { nick: String, address: String, channel: String, text: String },
+    // a private message sent inside a channel (Buzzen)
+    Query { nick: String, address: String, text: String },
+    Whisper { nick: String, address: String, channel: String, text: String },
+    // target is None for actions sent in a query
+    Action { nick: String, address: String, target: Option<String>, text: String },
+    CtcpRequest { nick: String, address: String, request: String },
+    CtcpReply { nick: String, address: String, ctcp_type: String, reply: String },
+    // target is None for private notices
+    Notice { nick: String, address: String, target: Option<String>, text: String },
+    ServerNotice { target: Option<String>, text: String },
+    ServerCtcp { ctcp_type: String, text: String },
+    Mode { nick: String, address: String, target: String, modes: String },
+    Numeric { numeric: String, text: String },
+    Away { nick: String, text: String },
+    Unaway { nick: String, text: String },
+    // the channel welcome message (Buzzen)
+    Welcome { channel: String, text: String },
+    // connection state, from IrcClient::run
+    Connected { server: String },
+    Disconnected { reason: String },
+    // server is the one the next attempt goes to
+    Reconnecting { server: String, attempt: u32, delay_secs: f64 },
+    // IRCv3 caps the server enabled or dropped, see caps.rs
+    CapsChanged { added: Vec<String>, removed: Vec<String> },
+    // SASL did not work out, registration carries on without it
+    SaslFailed { reason: String },
+    // a new config was taken on, see reload.rs
+    ConfigReloaded { changes: Vec<String> },
+    // round trip of a keepalive PING, see keepalive.rs
+    Lag { millis: u64 },
+    // a line from the server as it came in, before any of the above
+    Received { line: String },
+    // a line we sent to the server, with passwords masked
+    Sent { line: String },
+    // a line that was not valid UTF-8
+    DecodeFallback { line: String, decoding: Decoding },
+    Malformed { line: String, reason: String },
+    Unknown { line: String },
+}
+
+impl Event {
+    // The channel the event happened in, None for everything else.
+    pub fn channel(&self) -> Option<&str> {
+        match self {
+            Event::Join { channel, .. }
+            | Event::Part { channel, .. }
+            | Event::Kick { channel, .. }
+            | Event::Privmsg { channel, .. }
+            | Event::Whisper { channel, .. }
+            | Event::Welcome { channel, .. } => Some(channel),
+            Event::Action { target, .. } | Event::Notice { target, .. } | Event::ServerNotice { target, .. } => {
+                target.as_deref().filter(|target| is_channel(target))
+            },
+            Event::Mode { target, .. } => Some(target.as_str()).filter(|target| is_channel(target)),
+            _ => None,
+        }
+    }
+
+    // Build the event for a parsed line.
+    //
+    // Buzzen differs from RFC 2812 in a few places, all handled here:
+    //   JOIN     registered users carry profile data before the channel
+    //            :<NICK!USER@ADDRESS> JOIN <PROFILE_DATA> :<CHANNEL>
+    //   PRIVMSG  a private message inside a channel names the recipient
+    //            :<NICK!USER@ADDRESS> PRIVMSG <CHANNEL> <NAME> :<MESSAGE>
+    //            and the channel welcome message comes from the channel itself
+    //            :%#Channelname PRIVMSG %#ChannelName :<WelcomeMessage>
+    //   NOTICE   private notices are scoped to a channel the same way
+    //            :<NICK!USER@ADDRESS> NOTICE <CHANNEL> <NICKNAME> :<MESSAGE>
+    //   WHISPER  :<NICK!USER@ADDRESS> WHISPER <CHANNEL> <TARGET> :<MESSAGE>
+    //   821/822  unaway/away notifications come from the user's own prefix
+    //            :<NICK!USER@ADDRESS> 822 <CHANNEL> :<MESSAGE>
+    //
+    // IrcMessage::parse has already checked that the params indexed below exist.
+    pub fn from_message(line: &str, msg: &IrcMessage) -> Event {
+        let nick = msg.nick().to_string();
+        let address = msg.address();
+
+        match msg.command.as_str() {
+            "JOIN" => {
+                // the channel is always the last param, profile data or not
+                let channel = msg.text().to_string();
+                Event::Join { nick, address, channel }
+            },
+            "PART" => { // :<NICK!USER@ADDRESS> PART <CHANNEL>
+                let channel = msg.param(0).unwrap_or(msg.text()).to_string();
+                Event::Part { nick, address, channel }
+            },
+            "QUIT" => {
+                let reason = trim_trailing_whitespace(msg.text());
+                Event::Quit { nick, address, reason }
+            },
+            "NICK" => {
+                let new_nick = msg.text().to_string();
+                Event::Nick { nick, address, new_nick }
+            },
+            "KICK" => { // :<NICK!USER@ADDRESS> KICK <CHANNEL> <TARGET> :<REASON>
+                let channel = msg.params[0].clone();
+                let target = msg.params[1].clone();
+                let reason = trim_trailing_whitespace(&msg.text_from(2));
+                Event::Kick { nick, address, channel, target, reason }
+            },
+            "NOTICE" => {
+                let target = msg.params[0].clone();
+                let text = strip_style(&trim_trailing_whitespace(msg.text()));
+                if msg.prefix.as_ref().is_some_and(|prefix| prefix.is_user()) {
+                    if let Some((ctcp_type, reply)) = ctcp(&text, '\u{0001}') {
+                        Event::CtcpReply { nick, address, ctcp_type, reply }
+                    } else if msg.params.len() > 1 { // because buzzen is weird
+                        Event::Notice { nick, address, target: None, text }
+                    } else {
+                        Event::Notice { nick, address, target: Some(target), text }
+                    }
+                } else if let Some((ctcp_type, text)) = ctcp(&text, '\u{0001}') {
+                    Event::ServerCtcp { ctcp_type, text }
+                } else if is_channel(&target) {
+                    Event::ServerNotice { target: Some(target), text }
+                } else {
+                    Event::ServerNotice { target: None, text }
+                }
+            },
+            "MODE" => {
+                let target = msg.params[0].clone();
+                let modes = trim_trailing_whitespace(&msg.text_from(1));
+                Event::Mode { nick, address, target, modes }
+            },
+            "WHISPER" => {
+                let channel = msg.params[0].clone();
+                let text = strip_style(&trim_trailing_whitespace(msg.text()));
+                Event::Whisper { nick, address, channel, text }
+            },
+            "PRIVMSG" => {
+                let target = msg.params[0].clone();
+                // \x02 is bold and gone by now, so only \x01 marks a CTCP
+                let text = strip_style(&trim_trailing_whitespace(msg.text()));
+                if is_channel(&nick) {
+                    Event::Welcome { channel: target, text }
+                } else {
+                    // because buzzen is weird, a query carries the recipient as an extra param
+                    let query = msg.params.len() > 1;
+                    if let Some((ctcp_type, args)) = ctcp(&text, '\u{0001}') {
+                        if ctcp_type == "ACTION" {
+                            let target = if query { None } else { Some(target) };
+                            Event::Action { nick, address, target, text: args }
+                        } else {
+                            let request = text.trim_matches('\u{0001}').to_string();
+                            Event::CtcpRequest { nick, address, request }
+                        }
+                    } else if query {
+                        Event::Query { nick, address, text }
+                    } else {
+                        Event::Privmsg { nick, address, channel: target, text }
+                    }
+                }
+            },
+            _ => match msg.numeric() {
+                Some(numeric) => {
+                    let numeric = format!("{:03}", numeric);
+                    match numeric.as_str() {
+                        "821" => { /* :UNAWAY MESSAGE */
+                            // :<NICK!USER@ADDRESS> 821 <CHANNEL> :<MESSAGE>
+                            let text = trim_trailing_whitespace(&strip_style(msg.text()));
+                            Event::Unaway { nick, text }
+                        },
+                        "822" => { /* :AWAY MESSAGE */
+                            // :<NICK!USER@ADDRESS> 822 <CHANNEL> :<MESSAGE>
+                            let text = trim_trailing_whitespace(&strip_style(msg.text()));
+                            Event::Away { nick, text }
+                        },
+                        // the first param is always our own nick
+                        _ => Event::Numeric { numeric, text: msg.text_from(1) },
+                    }
+                },
+                None => Event::Unknown { line: line.to_string() },
+            },
+        }
+    }
+}
+
+impl From<ParseError> for Event {
+    fn from(err: ParseError) -> Self {
+        Event::Malformed { line: err.line, reason: err.reason }
+    }
+}
+
+// What a channel name starts with: # and & from RFC 2812, + for modeless
+// channels, % on Buzzen.
+pub const CHANNEL_PREFIXES: [char; 4] = ['#', '%', '&', '+'];
+
+pub fn is_channel(target: &str) -> bool {
+    target.starts_with(CHANNEL_PREFIXES)
+}
+
+// Split a CTCP message wrapped in `delimiter` into its upper-cased type and
+// the rest of the text.
+fn ctcp(text: &str, delimiter: char) -> Option<(String, String)> {
+    if text.len() > 1 && text.starts_with(delimiter) && text.ends_with(delimiter) {
+        let inner = &text[1..text.len() - 1];
+        let (ctcp_type, rest) = inner.split_once(' ').unwrap_or((inner, ""));
+        Some((ctcp_type.to_uppercase(), rest.to_string()))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(line: &str) -> Event {
+        Event::from_message(line, &IrcMessage::parse(line).unwrap())
+    }
+
+    fn s(text: &str) -> String {
+        text.to_string()
+    }
+
+    #[test]
+    fn lines_become_events() {
+        let cases = [
+            (":bob!u@h JOIN #a", Event::Join { nick: s("bob"), address: s("u@h"), channel: s("#a") }),
+            // Buzzen profile data before the channel
+            (":bob!u@h JOIN H,U,GY :%#Lobby", Event::Join { nick: s("bob"), address: s("u@h"), channel: s("%#Lobby") }),
+            (":bob!u@h PART %#Lobby", Event::Part { nick: s("bob"), address: s("u@h"), channel: s("%#Lobby") }),
+            (":bob!u@h KICK #a bot :go away ", Event::Kick { nick: s("bob"), address: s("u@h"), channel: s("#a"), target: s("bot"), reason: s("go away") }),
+            (":bob!u@h PRIVMSG #a :\u{0002}hello\u{0002} there", Event::Privmsg { nick: s("bob"), address: s("u@h"), channel: s("#a"), text: s("hello there") }),
+            // a query inside a channel names the recipient
+            (":bob!u@h PRIVMSG %#Lobby bot :psst", Event::Query { nick: s("bob"), address: s("u@h"), text: s("psst") }),
+            (":%#Lobby PRIVMSG %#Lobby :Welcome to the Lobby", Event::Welcome { channel: s("%#Lobby"), text: s("Welcome to the Lobby") }),
+            (":bob!u@h PRIVMSG #a :\u{0001}ACTION waves\u{0001}", Event::Action { nick: s("bob"), address: s("u@h"), target: Some(s("#a")), text: s("waves") }),
+            (":bob!u@h PRIVMSG %#Lobby bot :\u{0001}ACTION waves\u{0001}", Event::Action { nick: s("bob"), address: s("u@h"), target: None, text: s("waves") }),
+            (":bob!u@h PRIVMSG bot :\u{0001}version\u{0001}", Event::CtcpRequest { nick: s("bob"), address: s("u@h"), request: s("version") }),
+            // all in bold, not a CTCP
+            (":bob!u@h PRIVMSG bot :\u{0002}VERSION\u{0002}", Event::Privmsg { nick: s("bob"), address: s("u@h"), channel: s("bot"), text: s("VERSION") }),
+            (":bob!u@h NOTICE bot :\u{0001}VERSION mIRC\u{0001}", Event::CtcpReply { nick: s("bob"), address: s("u@h"), ctcp_type: s("VERSION"), reply: s("mIRC") }),
+            (":bob!u@h NOTICE #a :heads up", Event::Notice { nick: s("bob"), address: s("u@h"), target: Some(s("#a")), text: s("heads up") }),
+            // a private notice scoped to a channel
+            (":bob!u@h NOTICE %#Lobby bot :psst", Event::Notice { nick: s("bob"), address: s("u@h"), target: None, text: s("psst") }),
+            (":srv NOTICE * :*** Looking up your hostname", Event::ServerNotice { target: None, text: s("*** Looking up your hostname") }),
+            (":srv NOTICE #a :maintenance soon", Event::ServerNotice { target: Some(s("#a")), text: s("maintenance soon") }),
+            (":srv NOTICE bot :\u{0001}TIME now\u{0001}", Event::ServerCtcp { ctcp_type: s("TIME"), text: s("now") }),
+            (":bob!u@h WHISPER %#Lobby bot :secret", Event::Whisper { nick: s("bob"), address: s("u@h"), channel: s("%#Lobby"), text: s("secret") }),
+            (":bob!u@h MODE #a +o bot", Event::Mode { nick: s("bob"), address: s("u@h"), target: s("#a"), modes: s("+o bot") }),
+            (":bob!u@h 822 %#Lobby :\u{0002}lunch\u{0002} ", Event::Away { nick: s("bob"), text: s("lunch") }),
+            (":bob!u@h 821 %#Lobby :back", Event::Unaway { nick: s("bob"), text: s("back") }),
+            (":srv 001 bot :Welcome to the network", Event::Numeric { numeric: s("001"), text: s("Welcome to the network") }),
+            (":srv FROBNICATE bot", Event::Unknown { line: s(":srv FROBNICATE bot") }),
+        ];
+        for (line, expected) in cases {
+            assert_eq!(event(line), expected, "{}", line);
+        }
+    }
+
+    #[test]
+    fn channel_of_an_event() {
+        assert_eq!(event(":bob!u@h PRIVMSG #a :hi").channel(), Some("#a"));
+        assert_eq!(event(":bob!u@h MODE bob +i").channel(), None);
+        assert_eq!(event(":bob!u@h NOTICE &local :hi").channel(), Some("&local"));
+        assert_eq!(event(":bob!u@h QUIT :bye").channel(), None);
+    }
+}
@@ -0,0 +1,72 @@
+use chrono::prelude::*;
+use colored::*;
+use regex::Regex;
+
+// Print a line to the terminal with a timestamp, colored by event type
+pub fn printall(event: &str, text: &str) {
+    let now = Local::now();
+    let timestamp = now.format("[%H:%M:%S]").to_string();
+    match event {
+        "away" => {     
+            let blueish = CustomColor::new(50, 109, 168);
+            println!("{} {}", timestamp, text.custom_color(blueish));
+        },
+        "unaway" => {     
+            let blueish = CustomColor::new(50, 109, 168);
+            println!("{} {}", timestamp, text.custom_color(blueish));
+        },
+        "alert" => println!("{} {}", timestamp, text.yellow()),
+        "alert_blue" => println!("{} {}", timestamp, text.bright_blue()),
+        "sctcp" => {            
+            let reddish = CustomColor::new(209, 82, 109);
+            println!("{} {}", timestamp, text.custom_color(reddish));
+        },
+        "ctcpreply" => {            
+            let orange = CustomColor::new(255, 165, 0);
+            println!("{} {}", timestamp, text.custom_color(orange));
+        },
+        "ctcprequest" => {            
+            let orange = CustomColor::new(255, 165, 0);
+            println!("{} {}", timestamp, text.custom_color(orange));
+        },
+        "snotice" => println!("{} {}", timestamp, text.bright_red()),
+        "join" => println!("{} {}", timestamp, text.green()),
+        "part" => println!("{} {}", timestamp, text.green()),
+        "welcome" => println!("{} {}", timestamp, text.bright_green()),
+        "quit" => println!("{} {}", timestamp, text.green()),
+        "kick" => println!("{} {}", timestamp, text.red()),
+        "notice" => println!("{} {}", timestamp, text.bright_magenta()),
+        "nick" => println!("{} {}", timestamp, text.bright_blue()),
+        "numeric" => {
+            let grey = CustomColor::new(128, 128, 128);
+            println!("{} {}", timestamp, text.custom_color(grey));
+        },
+        "mode" => println!("{} {}", timestamp, text.cyan()),
+        "privmsg" => println!("{} {}", timestamp, text.bright_white()),
+        "query" => println!("{} {}", timestamp, text.white()),
+        "action" => println!("{} {}", timestamp, text.italic().purple()),
+        _ => println!("{} {}", timestamp, text)
+    }
+}
+
+pub fn strip_style(value: &str) -> String {
+    // strip Buzzen [style] tags
+    let style_regex = Regex::new(r"\[(?:/)?style(?:[^\]]+)?\]").unwrap();
+    let result = style_regex.replace_all(value, "");
+
+    // strip mIRC codes (underline, bold, color, ect...)
+    let special_regex = Regex::new(r"(\u{0003}(\d(\d)?(,(\d(\d)?)?)?)?|\u{001F}|\u{0002}|\u{000F}|\u{0016})").unwrap();
+    special_regex.replace_all(&result, "").to_string()
+}
+
+pub fn trim_trailing_whitespace(input: &str) -> String {
+    input.chars()
+        .rev() // Start from the end of the string
+        .skip_while(|&c| c.is_whitespace()) // Skip whitespaces
+        .collect::<String>() // Collect the characters into a string
+        .chars() // Reversed, so reverse it again to get original order
+        .rev()
+        .collect::<String>() // Collect the characters into a string
+}
+
+//
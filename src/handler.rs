@@ -0,0 +1,204 @@
+// Plug-in behavior for the client.
+//
+// Implement EventHandler, override the on_* methods you care about and
+// register it with IrcClient::add_handler. Handlers run in the order they were
+// added, for every event, and can reply through ctx.sender.
+
+use async_trait::async_trait;
+use std::sync::{Arc, RwLock};
+use tokio::sync::broadcast;
+
+use crate::caps::Caps;
+use crate::codec::Decoding;
+use crate::config::BuzzenConfig;
+use crate::event::{Envelope, Event};
+use crate::sender::Sender;
+use crate::tags::Tags;
+
+// What a handler gets besides the event itself.
+#[derive(Clone)]
+pub struct Context {
+    // name of the network the event came from, see BuzzenConfig::network_name
+    pub network: String,
+    pub sender: Sender,
+    // IRCv3 caps the server offers and has enabled
+    pub caps: Caps,
+    // message tags of the line behind the current event
+    pub tags: Tags,
+}
+
+// Hands every event to the registered handlers and broadcast subscribers.
+// Shared by the reader and the writer task, so events from both end up in
+// the same place.
+#[derive(Clone)]
+pub(crate) struct Dispatcher {
+    handlers: Arc<RwLock<Vec<Arc<dyn EventHandler>>>>,
+    events: broadcast::Sender<Envelope>,
+    context: Context,
+}
+
+impl Dispatcher {
+    pub(crate) fn new(context: Context) -> Self {
+        let (events, _) = broadcast::channel(256);
+        Dispatcher { handlers: Arc::new(RwLock::new(Vec::new())), events, context }
+    }
+
+    pub(crate) fn add_handler(&self, handler: Arc<dyn EventHandler>) {
+        self.handlers.write().unwrap_or_else(|err| err.into_inner()).push(handler);
+    }
+
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<Envelope> {
+        self.events.subscribe()
+    }
+
+    pub(crate) fn context(&self) -> &Context {
+        &self.context
+    }
+
+    // let every handler see a reloaded config
+    pub(crate) fn reload(&self, config: &BuzzenConfig) {
+        let handlers = self.handlers.read().unwrap_or_else(|err| err.into_inner()).clone();
+        for handler in handlers {
+            handler.reload(config);
+        }
+    }
+
+    pub(crate) async fn emit(&self, event: Event) {
+        self.emit_tagged(event, Tags::new()).await;
+    }
+
+    pub(crate) async fn emit_tagged(&self, event: Event, tags: Tags) {
+        let context = Context { tags: tags.clone(), ..self.context.clone() };
+        // take a snapshot so the lock isn't held while handlers run
+        let handlers = self.handlers.read().unwrap_or_else(|err| err.into_inner()).clone();
+        for handler in handlers {
+            handler.on_event(&context, &event).await;
+        }
+        // no subscribers is not an error, the event is simply dropped
+        let _ = self.events.send(Envelope { network: self.context.network.clone(), event, tags });
+    }
+}
+
+#[allow(unused_variables)]
+#[async_trait]
+pub trait EventHandler: Send + Sync {
+    // Called for every event. The default routes to the on_* method for
+    // the event's type; override this to see everything in one place, or to
+    // filter events and pass the rest on with route().
+    async fn on_event(&self, ctx: &Context, event: &Event) {
+        route(self, ctx, event).await
+    }
+
+    // The config was reloaded, pick up any settings the handler uses.
+    fn reload(&self, config: &BuzzenConfig) {}
+
+    async fn on_join(&self, ctx: &Context, nick: &str, address: &str, channel: &str) {}
+
+    async fn on_part(&self, ctx: &Context, nick: &str, address: &str, channel: &str) {}
+
+    async fn on_quit(&self, ctx: &Context, nick: &str, address: &str, reason: &str) {}
+
+    async fn on_nick(&self, ctx: &Context, nick: &str, address: &str, new_nick: &str) {}
+
+    async fn on_kick(&self, ctx: &Context, nick: &str, address: &str, knick: &str, channel: &str, reason: &str) {}
+
+    async fn on_privmsg(&self, ctx: &Context, nick: &str, address: &str, channel: &str, message: &str) {}
+
+    // a private message sent inside a channel (Buzzen)
+    async fn on_query(&self, ctx: &Context, nick: &str, address: &str, message: &str) {}
+
+    async fn on_whisper(&self, ctx: &Context, nick: &str, address: &str, channel: &str, message: &str) {}
+
+    // target is None for actions sent in a query
+    async fn on_action(&self, ctx: &Context, nick: &str, address: &str, target: Option<&str>, message: &str) {}
+
+    async fn on_ctcp_request(&self, ctx: &Context, nick: &str, address: &str, request: &str) {}
+
+    async fn on_ctcp_reply(&self, ctx: &Context, nick: &str, address: &str, ctcp_type: &str, ctcp_reply: &str) {}
+
+    // target is None for private notices
+    async fn on_notice(&self, ctx: &Context, nick: &str, address: &str, target: Option<&str>, message: &str) {}
+
+    async fn on_server_notice(&self, ctx: &Context, target: Option<&str>, message: &str) {}
+
+    async fn on_server_ctcp(&self, ctx: &Context, ctcp_type: &str, ctcp_reply: &str) {}
+
+    async fn on_mode(&self, ctx: &Context, nick: &str, address: &str, target: &str, modes: &str) {}
+
+    async fn on_numeric(&self, ctx: &Context, numeric: &str, message: &str) {}
+
+    async fn on_away(&self, ctx: &Context, nick: &str, message: &str) {}
+
+    async fn on_unaway(&self, ctx: &Context, nick: &str, message: &str) {}
+
+    async fn on_welcome(&self, ctx: &Context, channel: &str, message: &str) {}
+
+    async fn on_connected(&self, ctx: &Context, server: &str) {}
+
+    async fn on_disconnected(&self, ctx: &Context, reason: &str) {}
+
+    async fn on_reconnecting(&self, ctx: &Context, server: &str, attempt: u32, delay_secs: f64) {}
+
+    async fn on_caps_changed(&self, ctx: &Context, added: &[String], removed: &[String]) {}
+
+    async fn on_sasl_failed(&self, ctx: &Context, reason: &str) {}
+
+    async fn on_config_reloaded(&self, ctx: &Context, changes: &[String]) {}
+
+    async fn on_lag(&self, ctx: &Context, millis: u64) {}
+
+    async fn on_received(&self, ctx: &Context, line: &str) {}
+
+    async fn on_sent(&self, ctx: &Context, line: &str) {}
+
+    async fn on_decode_fallback(&self, ctx: &Context, line: &str, decoding: &Decoding) {}
+
+    async fn on_parse_error(&self, ctx: &Context, line: &str, reason: &str) {}
+
+    async fn on_unsupported(&self, ctx: &Context, line: &str) {}
+}
+
+// Call the on_* method of handler that matches the event's type.
+pub async fn route<H: EventHandler + ?Sized>(handler: &H, ctx: &Context, event: &Event) {
+    match event {
+        Event::Join { nick, address, channel } => handler.on_join(ctx, nick, address, channel).await,
+        Event::Part { nick, address, channel } => handler.on_part(ctx, nick, address, channel).await,
+        Event::Quit { nick, address, reason } => handler.on_quit(ctx, nick, address, reason).await,
+        Event::Nick { nick, address, new_nick } => handler.on_nick(ctx, nick, address, new_nick).await,
+        Event::Kick { nick, address, channel, target, reason } => {
+            handler.on_kick(ctx, nick, address, target, channel, reason).await
+        },
+        Event::Privmsg { nick, address, channel, text } => handler.on_privmsg(ctx, nick, address, channel, text).await,
+        Event::Query { nick, address, text } => handler.on_query(ctx, nick, address, text).await,
+        Event::Whisper { nick, address, channel, text } => handler.on_whisper(ctx, nick, address, channel, text).await,
+        Event::Action { nick, address, target, text } => {
+            handler.on_action(ctx, nick, address, target.as_deref(), text).await
+        },
+        Event::CtcpRequest { nick, address, request } => handler.on_ctcp_request(ctx, nick, address, request).await,
+        Event::CtcpReply { nick, address, ctcp_type, reply } => {
+            handler.on_ctcp_reply(ctx, nick, address, ctcp_type, reply).await
+        },
+        Event::Notice { nick, address, target, text } => {
+            handler.on_notice(ctx, nick, address, target.as_deref(), text).await
+        },
+        Event::ServerNotice { target, text } => handler.on_server_notice(ctx, target.as_deref(), text).await,
+        Event::ServerCtcp { ctcp_type, text } => handler.on_server_ctcp(ctx, ctcp_type, text).await,
+        Event::Mode { nick, address, target, modes } => handler.on_mode(ctx, nick, address, target, modes).await,
+        Event::Numeric { numeric, text } => handler.on_numeric(ctx, numeric, text).await,
+        Event::Away { nick, text } => handler.on_away(ctx, nick, text).await,
+        Event::Unaway { nick, text } => handler.on_unaway(ctx, nick, text).await,
+        Event::Welcome { channel, text } => handler.on_welcome(ctx, channel, text).await,
+        Event::Connected { server } => handler.on_connected(ctx, server).await,
+        Event::Disconnected { reason } => handler.on_disconnected(ctx, reason).await,
+        Event::Reconnecting { server, attempt, delay_secs } => handler.on_reconnecting(ctx, server, *attempt, *delay_secs).await,
+        Event::CapsChanged { added, removed } => handler.on_caps_changed(ctx, added, removed).await,
+        Event::SaslFailed { reason } => handler.on_sasl_failed(ctx, reason).await,
+        Event::ConfigReloaded { changes } => handler.on_config_reloaded(ctx, changes).await,
+        Event::Lag { millis } => handler.on_lag(ctx, *millis).await,
+        Event::Received { line } => handler.on_received(ctx, line).await,
+        Event::Sent { line } => handler.on_sent(ctx, line).await,
+        Event::DecodeFallback { line, decoding } => handler.on_decode_fallback(ctx, line, decoding).await,
+        Event::Malformed { line, reason } => handler.on_parse_error(ctx, line, reason).await,
+        Event::Unknown { line } => handler.on_unsupported(ctx, line).await,
+    }
+}
@@ -0,0 +1,166 @@
+// Client-side PINGs. When nothing has been read for a while the client pings
+// the server with a token and gives up on the connection if nothing at all
+// is read in time. Any line proves the connection is alive, the matching
+// PONG is only needed to measure the lag. Without this a silently
+// dead TCP connection would leave the bot waiting in read() forever.
+
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::time::Instant;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KeepaliveConfig {
+    // ping after this long without reading anything, 0 turns keepalive off
+    pub idle_secs: f64,
+    // how long to wait for the PONG before the connection counts as dead
+    pub timeout_secs: f64,
+}
+
+impl Default for KeepaliveConfig {
+    fn default() -> Self {
+        KeepaliveConfig { idle_secs: 60.0, timeout_secs: 120.0 }
+    }
+}
+
+pub(crate) enum Tick {
+    Ping(String),
+    TimedOut(Duration),
+}
+
+pub(crate) struct Keepalive {
+    config: KeepaliveConfig,
+    last_read: Instant,
+    // token and send time of the last PING, until its PONG
+    pending: Option<(String, Instant)>,
+    counter: u64,
+}
+
+impl Keepalive {
+    pub(crate) fn new(config: KeepaliveConfig) -> Self {
+        Keepalive { config, last_read: Instant::now(), pending: None, counter: 0 }
+    }
+
+    // new settings, a PING in flight is still waited for
+    pub(crate) fn set_config(&mut self, config: KeepaliveConfig) {
+        self.config = config;
+    }
+
+    // start over for a new connection
+    pub(crate) fn reset(&mut self) {
+        self.last_read = Instant::now();
+        self.pending = None;
+    }
+
+    pub(crate) fn activity(&mut self) {
+        self.last_read = Instant::now();
+    }
+
+    // When tick() has something to do next, None when keepalive is off.
+    pub(crate) fn deadline(&self) -> Option<Instant> {
+        if self.config.idle_secs <= 0.0 {
+            return None;
+        }
+        Some(match self.waiting() {
+            Some(sent) => sent + Duration::from_secs_f64(self.config.timeout_secs.max(0.0)),
+            None => self.last_read + Duration::from_secs_f64(self.config.idle_secs),
+        })
+    }
+
+    // when the PING went out, if nothing has been read since
+    fn waiting(&self) -> Option<Instant> {
+        self.pending.as_ref().map(|(_, sent)| *sent).filter(|sent| self.last_read <= *sent)
+    }
+
+    pub(crate) fn tick(&mut self) -> Option<Tick> {
+        let now = Instant::now();
+        if now < self.deadline()? {
+            return None;
+        }
+        if let Some(sent) = self.waiting() {
+            return Some(Tick::TimedOut(now - sent));
+        }
+        self.counter += 1;
+        let token = format!("rustyirc-{}", self.counter);
+        self.pending = Some((token.clone(), now));
+        Some(Tick::Ping(token))
+    }
+
+    // The lag, if this PONG answers our PING.
+    pub(crate) fn pong(&mut self, token: &str) -> Option<Duration> {
+        match &self.pending {
+            Some((pending, sent)) if pending == token => {
+                let lag = sent.elapsed();
+                self.pending = None;
+                Some(lag)
+            },
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::advance;
+
+    fn keepalive() -> Keepalive {
+        Keepalive::new(KeepaliveConfig { idle_secs: 60.0, timeout_secs: 120.0 })
+    }
+
+    fn ping(tick: Option<Tick>) -> String {
+        match tick {
+            Some(Tick::Ping(token)) => token,
+            _ => panic!("expected a PING"),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn ping_when_idle_then_time_out() {
+        let mut keepalive = keepalive();
+        advance(Duration::from_secs(59)).await;
+        assert!(keepalive.tick().is_none());
+        advance(Duration::from_secs(1)).await;
+        assert_eq!(ping(keepalive.tick()), "rustyirc-1");
+
+        // no second PING while waiting for the first
+        advance(Duration::from_secs(119)).await;
+        assert!(keepalive.tick().is_none());
+        advance(Duration::from_secs(1)).await;
+        assert!(matches!(keepalive.tick(), Some(Tick::TimedOut(waited)) if waited == Duration::from_secs(120)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn any_line_after_the_ping_counts() {
+        let mut keepalive = keepalive();
+        advance(Duration::from_secs(60)).await;
+        ping(keepalive.tick());
+        advance(Duration::from_secs(10)).await;
+        keepalive.activity();
+
+        // idle again from the line, not timed out from the PING
+        assert_eq!(keepalive.deadline(), Some(Instant::now() + Duration::from_secs(60)));
+        advance(Duration::from_secs(200)).await;
+        assert_eq!(ping(keepalive.tick()), "rustyirc-2");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn lag_from_the_matching_pong() {
+        let mut keepalive = keepalive();
+        advance(Duration::from_secs(60)).await;
+        let token = ping(keepalive.tick());
+        advance(Duration::from_millis(250)).await;
+        assert_eq!(keepalive.pong("someone-else"), None);
+        assert_eq!(keepalive.pong(&token), Some(Duration::from_millis(250)));
+        // answered, so a repeat is ignored
+        assert_eq!(keepalive.pong(&token), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn zero_idle_turns_it_off() {
+        let mut keepalive = Keepalive::new(KeepaliveConfig { idle_secs: 0.0, timeout_secs: 120.0 });
+        advance(Duration::from_secs(3600)).await;
+        assert_eq!(keepalive.deadline(), None);
+        assert!(keepalive.tick().is_none());
+    }
+}
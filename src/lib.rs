@@ -0,0 +1,47 @@
+// RustyIRC as a library: the IRC client, the line parser, the config file
+// and the text formatting helpers. The rustyirc binary is a thin terminal
+// front end on top of this.
+
+pub mod caps;
+pub mod channels;
+pub mod client;
+pub mod codec;
+pub mod config;
+pub mod console;
+pub mod event;
+pub mod format;
+pub mod handler;
+pub mod keepalive;
+pub mod login;
+pub mod message;
+#[cfg(test)]
+mod mock;
+pub mod proxy;
+pub mod ratelimit;
+pub mod reconnect;
+pub mod reload;
+pub mod sasl;
+pub mod secrets;
+pub mod sender;
+pub mod servers;
+pub mod setup;
+pub mod tags;
+pub mod terminal;
+pub mod transport;
+mod writer;
+
+pub use caps::Caps;
+pub use client::IrcClient;
+pub use channels::ChannelConfig;
+pub use config::{BuzzenConfig, Config, ConfigError};
+pub use console::ConsoleHandler;
+pub use event::{Envelope, Event};
+pub use format::{printall, strip_style, trim_trailing_whitespace};
+pub use handler::{Context, EventHandler};
+pub use login::{LoginStrategy, Network};
+pub use message::{IrcMessage, ParseError, Prefix};
+pub use reload::Reloader;
+pub use sender::Sender;
+pub use servers::ServerConfig;
+pub use tags::Tags;
+pub use terminal::Terminal;
@@ -0,0 +1,93 @@
+// How to log in once connected. Every network wants something a little
+// different, so the handshake is a LoginStrategy picked by "network" in
+// config.json. IrcClient::set_login takes a custom one.
+
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::config::BuzzenConfig;
+
+pub trait LoginStrategy: Send + Sync {
+    // The lines to send right after connecting, before anything queued.
+    fn handshake(&self, config: &BuzzenConfig) -> Vec<String>;
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Network {
+    #[default]
+    Buzzen,
+    // any RFC 2812 server
+    Irc,
+    Twitch,
+}
+
+impl Network {
+    // the name used in config.json
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Network::Buzzen => "buzzen",
+            Network::Irc => "irc",
+            Network::Twitch => "twitch",
+        }
+    }
+
+    pub fn login(&self) -> Arc<dyn LoginStrategy> {
+        match self {
+            Network::Buzzen => Arc::new(BuzzenLogin),
+            Network::Irc => Arc::new(Rfc2812Login),
+            Network::Twitch => Arc::new(TwitchLogin),
+        }
+    }
+}
+
+// ircwx1: the password goes over as an md5 digest next to the account email,
+// password_hash is that digest stored ahead of time
+pub struct BuzzenLogin;
+
+impl LoginStrategy for BuzzenLogin {
+    fn handshake(&self, config: &BuzzenConfig) -> Vec<String> {
+        let passwd = match &config.password_hash {
+            Some(hash) => hash.to_lowercase(),
+            None => format!("{:?}", md5::compute(&config.password)),
+        };
+        vec![
+            "AUTHTYPE ircwx1".to_string(),
+            format!("LOGINH {} {}", config.email, passwd),
+            format!("USER {} * 0 :RustBot", config.nickname),
+            "CLIENTMODE cd1".to_string(),
+        ]
+    }
+}
+
+// PASS (only with a password and no SASL), NICK, USER
+pub struct Rfc2812Login;
+
+impl LoginStrategy for Rfc2812Login {
+    fn handshake(&self, config: &BuzzenConfig) -> Vec<String> {
+        let mut lines = Vec::new();
+        if !config.password.is_empty() && config.sasl.is_none() {
+            lines.push(format!("PASS {}", config.password));
+        }
+        lines.push(format!("NICK {}", config.nickname));
+        lines.push(format!("USER {} 0 * :RustBot", config.nickname));
+        lines
+    }
+}
+
+// irc.chat.twitch.tv: the oauth token is the password, and membership, tags
+// and commands have to be requested or Twitch leaves them out
+pub struct TwitchLogin;
+
+impl LoginStrategy for TwitchLogin {
+    fn handshake(&self, config: &BuzzenConfig) -> Vec<String> {
+        let token = config.access_token.as_deref().unwrap_or(&config.password);
+        let token = if token.starts_with("oauth:") { token.to_string() } else { format!("oauth:{}", token) };
+        vec![
+            "CAP REQ :twitch.tv/membership twitch.tv/tags twitch.tv/commands".to_string(),
+            format!("PASS {}", token),
+            // Twitch nicks are always lowercase
+            format!("NICK {}", config.nickname.to_lowercase()),
+        ]
+    }
+}
@@ -1,704 +1,415 @@
-use colored::*;
-use std::{env, error::Error, fs};
-use std::io::{self, Write}; // Import io and Write trait
-use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpStream};
-use chrono::prelude::*;
-use serde::{Deserialize, Serialize};
-use regex::Regex;
-
-#[derive(Debug, Serialize, Deserialize)]
-struct BuzzenConfig {
-    nickname: String,
-    email: String,
-    password: String,
-    server: String,
-    channel: String,
+use clap::{Parser, Subcommand};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::{error::Error, fs, sync::atomic::{AtomicBool, Ordering}, time::Duration};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::sync::{broadcast::error::RecvError, mpsc};
+
+use rustyirc::channels::{self, ChannelConfig};
+use rustyirc::event::is_channel;
+use rustyirc::{printall, secrets, setup, BuzzenConfig, Config, ConsoleHandler, Event, IrcClient, Reloader, Sender, Terminal};
+
+// how long `send` waits to be logged in (and in the channel) before giving up
+const SEND_TIMEOUT: Duration = Duration::from_secs(60);
+
+// --json keeps stdout for the events, everything else goes to stderr
+static JSON: AtomicBool = AtomicBool::new(false);
+
+/// Simple IRC bot for Buzzen and other IRC networks
+#[derive(Parser, Clone)]
+#[command(version)]
+struct Cli {
+    /// Config file to use
+    #[arg(long, global = true, value_name = "PATH", default_value = "config.json")]
+    config: String,
+    /// Only use this one of the configured networks
+    #[arg(long, global = true, value_name = "NAME")]
+    network: Option<String>,
+    /// Connect to this host:port instead of the configured servers
+    #[arg(long, global = true)]
+    server: Option<String>,
+    /// Use this nickname instead of the configured one
+    #[arg(long, global = true)]
+    nick: Option<String>,
+    /// Join these channels instead of the configured ones (repeatable)
+    #[arg(long = "channel", global = true, value_name = "CHANNEL")]
+    channels: Vec<String>,
+    /// Also print every raw line from the server and the lag
+    #[arg(short, long, alias = "raw", global = true)]
+    verbose: bool,
+    /// Print without colors
+    #[arg(long, global = true)]
+    no_color: bool,
+    /// Print events as JSON, one per line, instead of text
+    #[arg(long, global = true)]
+    json: bool,
+    /// Run the setup wizard to create or edit the config
+    #[arg(long, global = true)]
+    setup: bool,
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-impl BuzzenConfig {
-    // Read configuration from file
-    fn from_file(filename: &str) -> Result<Self, Box<dyn Error>> {
-        let current_dir = env::current_dir()?;
-        let config_path = current_dir.join(filename);
+#[derive(Subcommand, Clone)]
+enum Command {
+    /// Connect and chat from the terminal (the default)
+    Run,
+    /// Check the config for problems and exit
+    CheckConfig,
+    /// Connect, send one message and quit
+    Send {
+        /// Channel or nick to send to
+        target: String,
+        #[arg(required = true)]
+        message: Vec<String>,
+    },
+}
 
-        match fs::read_to_string(&config_path) {
-            Ok(contents) => {
-                let config: BuzzenConfig = serde_json::from_str(&contents)?;
-                Ok(config)
-            }
-            Err(err) => {
-                if err.kind() == std::io::ErrorKind::NotFound {
-                    // File doesn't exist, create a default configuration and write it to the file
-                    let default_config = BuzzenConfig {
-                        nickname: String::new(),
-                        email: String::new(),
-                        password: String::new(),
-                        server: String::new(),
-                        channel: String::new(),
-                    };
-                    default_config.to_file(&config_path.into_os_string().into_string().unwrap())?;
-                    Ok(default_config)
-                } else {
-                    Err(Box::new(err))
-                }
-            }
+impl Cli {
+    // The command line wins over the config file. Overrides go to the network
+    // picked with --network, or the first one.
+    fn apply(&self, config: &mut Config) -> Result<(), String> {
+        if let Some(name) = &self.network {
+            let network = config.network(name).cloned().ok_or_else(|| {
+                let names: Vec<&str> = config.networks.iter().map(|network| network.network_name()).collect();
+                format!("There is no network called {}, only {}", name, names.join(", "))
+            })?;
+            config.networks = vec![network];
+        }
+        let Some(config) = config.networks.first_mut() else {
+            return Ok(());
+        };
+        if let Some(server) = &self.server {
+            config.server = server.clone();
+            config.servers.clear();
+        }
+        if let Some(nick) = &self.nick {
+            config.nickname = nick.clone();
+        }
+        if !self.channels.is_empty() {
+            let configured = config.channel_list();
+            config.channels = self
+                .channels
+                .iter()
+                .map(|name| channels::find(&configured, name).cloned().unwrap_or_else(|| ChannelConfig::new(name)))
+                .collect();
+            config.channel.clear();
         }
-    }
-
-    // Write configuration to file
-    fn to_file(&self, filename: &str) -> Result<(), Box<dyn Error>> {
-        let json = serde_json::to_string_pretty(self)?;
-        fs::write(filename, json)?;
         Ok(())
     }
 }
-// irc.chat.twitch.tv
-//  "CAP REQ :twitch.tv/membership twitch.tv/tags twitch.tv/commands";
-// ("PASS oauth:{}", config.accessToken);
-// ("NICK {}", config.nickname);
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    // Load settings from config.json
-    let config = BuzzenConfig::from_file("config.json")?;
-
-    let mut client = IrcClient::connect(&config.server, &config.channel).await?;
-
-    printall("alert", "Connected! Starting authentication process...");
-    client.write("AUTHTYPE ircwx1").await?;
-
-    let config = BuzzenConfig::from_file("config.json")?;
-
-    let passwd = md5::compute(config.password);
-
-    client.write(&format!("LOGINH {} {:?}", config.email, passwd)).await?;
-    client.write(&format!("USER {} * 0 :RustBot", config.nickname)).await?;
-    client.write("CLIENTMODE cd1").await?;
-
-    let terminal = tokio::spawn(async move {
-        loop {
-            let mut input = String::new();
-            std::io::stdout().flush().expect("Failed to flush stdout");
-            std::io::stdin().read_line(&mut input).expect("Failed to read line");
-            
-            // Send the user input as a PRIVMSG to the IRC server
-            println!("Input: {}", input);
+    let cli = Cli::parse();
+    if cli.no_color {
+        colored::control::set_override(false);
+    }
+    JSON.store(cli.json, Ordering::Relaxed);
+    let command = cli.command.as_ref().unwrap_or(&Command::Run);
+
+    // Load settings from the config file, asking for them on the first run or with --setup
+    let config = match load_config(&cli, matches!(command, Command::Run)) {
+        Ok(config) => config,
+        Err(err) => {
+            report("alert", &err.to_string());
+            std::process::exit(1);
         }
-    });
-
-    let server = tokio::spawn(async move {
-        let _ = client.process_messages().await;
-    });
-
-    tokio::try_join!(terminal, server)?;
-
-    Ok(())
-}
-
-fn printall(event: &str, text: &str) {
-    let now = Local::now();
-    let timestamp = now.format("[%H:%M:%S]").to_string();
-    match event {
-        "away" => {     
-            let blueish = CustomColor::new(50, 109, 168);
-            println!("{} {}", timestamp, text.custom_color(blueish));
-        },
-        "unaway" => {     
-            let blueish = CustomColor::new(50, 109, 168);
-            println!("{} {}", timestamp, text.custom_color(blueish));
-        },
-        "alert" => println!("{} {}", timestamp, text.yellow()),
-        "alert_blue" => println!("{} {}", timestamp, text.bright_blue()),
-        "sctcp" => {            
-            let reddish = CustomColor::new(209, 82, 109);
-            println!("{} {}", timestamp, text.custom_color(reddish));
-        },
-        "ctcpreply" => {            
-            let orange = CustomColor::new(255, 165, 0);
-            println!("{} {}", timestamp, text.custom_color(orange));
-        },
-        "ctcprequest" => {            
-            let orange = CustomColor::new(255, 165, 0);
-            println!("{} {}", timestamp, text.custom_color(orange));
+    };
+
+    match command {
+        Command::Run => run(&cli, config).await,
+        Command::CheckConfig => {
+            report("info", &format!("{} is fine", cli.config));
+            for network in &config.networks {
+                let servers: Vec<String> = network.server_list().iter().map(|server| server.address()).collect();
+                report("info", &format!("{}: would connect to {} as {}", network.network_name(), servers.join(", then "), network.nickname));
+            }
+            Ok(())
         },
-        "snotice" => println!("{} {}", timestamp, text.bright_red()),
-        "join" => println!("{} {}", timestamp, text.green()),
-        "part" => println!("{} {}", timestamp, text.green()),
-        "welcome" => println!("{} {}", timestamp, text.bright_green()),
-        "quit" => println!("{} {}", timestamp, text.green()),
-        "kick" => println!("{} {}", timestamp, text.red()),
-        "notice" => println!("{} {}", timestamp, text.bright_magenta()),
-        "nick" => println!("{} {}", timestamp, text.bright_blue()),
-        "numeric" => {
-            let grey = CustomColor::new(128, 128, 128);
-            println!("{} {}", timestamp, text.custom_color(grey));
+        Command::Send { target, message } => {
+            // the first network, or the one picked with --network
+            let network = config.networks.into_iter().next().unwrap_or_default();
+            if let Err(err) = send(&cli, network, target, &message.join(" ")).await {
+                report("alert", &err.to_string());
+                std::process::exit(1);
+            }
+            Ok(())
         },
-        "mode" => println!("{} {}", timestamp, text.cyan()),
-        "privmsg" => println!("{} {}", timestamp, text.bright_white()),
-        "query" => println!("{} {}", timestamp, text.white()),
-        "action" => println!("{} {}", timestamp, text.italic().purple()),
-        _ => println!("{} {}", timestamp, text)
     }
 }
 
-struct IrcClient {
-    stream: TcpStream,
-    message: String,
-    nickname: String,
-    address: String,
-    channel: String,
-}
-
-impl IrcClient {
-    pub async fn connect(server: &str, channel: &str) -> Result<Self, Box<dyn Error>> {
-        let stream = TcpStream::connect(server).await?;
-        let channel = channel.to_string();
-        Ok(IrcClient { stream, message: String::new(), nickname: String::new(), address: String::new(),  channel })
+// printall, or just the text on stderr with --json
+fn report(kind: &str, text: &str) {
+    if JSON.load(Ordering::Relaxed) {
+        eprintln!("{}", text);
+    } else {
+        printall(kind, text);
     }
+}
 
-    pub async fn write(&mut self, data: &str) -> io::Result<usize> {
-        if !data.starts_with("PONG") {
-            if data.starts_with("LOGIN") {
-                printall("default", "<< LOGINH ********** ***********")
-            } else {
-                printall("default", &format!("<< {}", data))
-            }
-        }
-        self.stream.write(&format!("{}\n", data).as_bytes()).await
-    }
+// The config file with the command line applied, checked. The wizard only
+// runs for `run`, the other commands shouldn't stop to ask questions.
+fn load_config(cli: &Cli, interactive: bool) -> Result<Config, Box<dyn Error>> {
+    let interactive = interactive && !cli.json && setup::is_interactive();
+    let config = match Config::read(&cli.config)? {
+        Some(config) if !cli.setup => config,
+        existing if interactive => {
+            let config = run_setup(existing)?;
+            config.to_file(&cli.config)?;
+            report("info", &format!("Saved {}", cli.config));
+            config
+        },
+        // writes a template and tells the user to fill it in
+        None if !cli.setup => Config::from_file(&cli.config)?,
+        _ => return Err("--setup needs a terminal to ask questions in".into()),
+    };
+    finish_config(cli, config)
+}
 
-    pub async fn read(&mut self) -> io::Result<()> {
-        let mut buffer = [0; 4096];
-        let bytes_read = self.stream.read(&mut buffer).await.unwrap();
-        if bytes_read == 0 {
-            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Connection closed"));
+// the wizard for a new config, or for each network in turn
+fn run_setup(existing: Option<Config>) -> std::io::Result<Config> {
+    let Some(mut config) = existing else {
+        return Ok(Config { networks: vec![setup::run(None, true)?] });
+    };
+    let several = config.networks.len() > 1;
+    for network in &mut config.networks {
+        if several {
+            println!("Network {}:", network.network_name());
         }
-        let str = std::str::from_utf8(&buffer[..bytes_read]).unwrap();
-        self.message.push_str(str);
-        Ok(())
+        *network = setup::run(Some(network), !several)?;
     }
+    Ok(config)
+}
 
-    pub async fn process_messages(&mut self) -> io::Result<()> {
-        loop {
-            self.read().await?;
-            while let Some(pos) = self.message.find('\n') {
-                let line = self.message[..pos].trim_end_matches('\n').to_string();
-                
-                if line.is_empty() {
-                    self.message.clear();
-                    break;
-                }
-                // print each line in its unparsed form
-                 //printall("default", &format!(">> {}", line));
-
-                if line.starts_with("PING") {
-                    let pong_msg = line.replace("PING", "PONG");
-                    self.write(&pong_msg).await?;
-                } else {
-                    let parts: Vec<&str> = line.split(' ').collect();
-                    if parts.len() >= 2 {
-                        match parts[1] {
-                            "JOIN" => {
-                                let sender = parts[0].split('!').next().unwrap();
-                                let sender = &sender[1..];
-                                let address = parts[0].split('!').nth(1).unwrap();
-                                // because buzzen is weird registered account join
-                                if parts[2].starts_with(':') {
-                                    // :<NICK!USER@ADDRESS> JOIN <PROFILE_DATA> :<CHANNEL>
-                                    let channel = &parts[2][1..];    
-                                    self.on_join(sender, address, channel).await?;
-                                } else { // guest join
-                                    // :<NICK!USER@ADDRESS> JOIN :<CHANNEL>
-                                    let channel = &parts[3][1..];    
-                                    self.on_join(sender, address, channel).await?;
-                                }
-                            },
-                            "PART" => { // :<NICK!USER@ADDRESS> PART <CHANNEL>
-                                let sender = parts[0].split('!').next().unwrap();
-                                let sender = &sender[1..];
-                                let address = parts[0].split('!').nth(1).unwrap();
-                                let channel = &parts[2];
-    
-                                self.on_part(sender, address, channel).await?;
-                            },
-                            "QUIT" => {
-                                let sender = parts[0].split('!').next().unwrap();
-                                let sender = &sender[1..];
-                                let address = parts[0].split('!').nth(1).unwrap();
-                                let msg_parts = &parts[2..];
-                                let mut msg = msg_parts.join(" ");
-                                msg.remove(0);
-                                let msg = trim_trailing_whitespace(&msg);   
-    
-                                self.on_quit(sender, address, &msg).await?;
-                            },
-                            "NICK" => {
-                                let sender = parts[0].split('!').next().unwrap();
-                                let sender = &sender[1..];
-                                let address = parts[0].split('!').nth(1).unwrap();
-                                let newnick = &parts[2][1..];
-    
-                                self.on_nick(sender, address, newnick).await?;
-                            },
-                            "KICK" => {
-                                let sender = parts[0].split('!').next().unwrap();
-                                let sender = &sender[1..];
-                                let address = parts[0].split('!').nth(1).unwrap();
-                                let channel = parts[2];
-                                let target = parts[3];
-                                let msg_parts = &parts[4..];
-                                let mut msg = msg_parts.join(" ");
-                                msg.remove(0);
-                                let msg = trim_trailing_whitespace(&msg);   
-                                self.on_kick(sender, address, target, channel, &msg).await?;
-                            },
-                            "NOTICE" => {
-                                let target = parts[2];
-
-                                if parts[0].contains('!') {                                    
-                                    let sender = parts[0].split('!').next().unwrap();
-                                    let sender = &sender[1..];                                    
-                                    let address = parts[0].split('!').nth(1).unwrap();                          
-                                    if parts[3].starts_with(':') {                                        
-                                        let msg_parts = &parts[3..];
-                                        let mut msg = msg_parts.join(" ");
-                                        msg.remove(0);
-                                        self.on_channel_notice(sender, address, target, &msg).await?;
-                                    } else { // because buzzen is weird
-                                        // :<NICK!USER@ADDRESS> NOTICE <CHANNEL> <NICKNAME> :<MESSAGE>
-                                        let msg_parts = &parts[4..];
-                                        let mut msg = msg_parts.join(" ");
-                                        msg.remove(0);
-                                        let msg = trim_trailing_whitespace(&msg);   
-                                        self.on_private_notice(sender, address, &msg).await?
-                                    }
-                                } else {     
-                                    let msg_parts = &parts[3..];
-                                    let mut msg = msg_parts.join(" ");
-                                    msg.remove(0);                  
-                                    let msg = trim_trailing_whitespace(&msg);                
-                                    if target.starts_with('%') || target.starts_with('#') {
-                                        self.on_channel_snotice(target, &msg).await?;
-                                    } else {
-                                        self.on_private_snotice(&msg).await?
-                                    }
-                                }
-    
-                            },
-                            "MODE" => {
-                                let sender = parts[0].split('!').next().unwrap();
-                                let sender = &sender[1..];
-                                let address = parts[0].split('!').nth(1).unwrap();
-                                let target = parts[2];
-                                let msg_parts = &parts[3..];
-                                let mut msg = msg_parts.join(" ");
-                                msg.remove(0);
-                                let msg = trim_trailing_whitespace(&msg);   
-                                if target.starts_with('%') || target.starts_with('#') {
-                                    self.on_chanmode(sender, address, target, &msg).await?;
-                                } else {
-                                    self.on_usermode(&msg).await?;
-                                }
-                            },
-                            "WHISPER" => {
-                                // :<NICK!USER@ADDRESS> WHISPER <CHANNEL> <TARGET> :<MESSAGE>
-                                let sender = parts[0].split('!').next().unwrap();
-                                let sender = &sender[1..];
-                                let address = parts[0].split('!').nth(1).unwrap();
-                                let target = parts[2];
-                                let msg_parts = &parts[4..];
-                                let mut msg = msg_parts.join(" ");
-                                msg.remove(0);  
-                                let msg = trim_trailing_whitespace(&msg);                     
-
-                                self.on_whisper(sender, address, target, &msg).await?;
-                            },
-                            "PRIVMSG" => {
-                                if parts[0].starts_with(":%") || parts[0].starts_with(":#") {
-                                    // Welcome message on buzzen is sent :%#Channelname PRIVMSG %#ChannelName :<WelcomeMessage>
-                                    let target = parts[2];
-                                    let msg_parts = &parts[3..];
-                                    let mut msg = msg_parts.join(" ");
-                                    msg.remove(0);
-                                    let msg = trim_trailing_whitespace(&msg);   
-                                    self.on_welcome(target, &msg).await?;
-                                } else {   
-                                    let sender = parts[0].split('!').next().unwrap();
-                                    let sender = &sender[1..];
-                                    let address = parts[0].split('!').nth(1).unwrap_or("");
-                                    let target = parts[2];     
+// The config file again, for a running client
+fn reload_config(cli: &Cli) -> Result<Config, Box<dyn Error>> {
+    let config = Config::read(&cli.config)?.ok_or_else(|| format!("{} is gone", cli.config))?;
+    finish_config(cli, config)
+}
 
-                                    //:<NICK!USER@ADDRESS> PRIVMSG <CHANNEL> :<MESSAGE> 
-                                    if parts[3].starts_with(':') {
-                                        let msg_parts = &parts[3..];
-                                        let mut msg = msg_parts.join(" ");
-                                        msg.remove(0);   
-                                        let msg = trim_trailing_whitespace(&msg);                
-                                        self.on_privmsg(sender, address, target, &msg).await?;
-                                    } else { // because buzzen is weird
-                                        // :<NICK!USER@ADDRESS> PRIVMSG <CHANNEL> <NAME> :<MESSAGE> 
-                                        let msg_parts = &parts[4..];
-                                        let mut msg = msg_parts.join(" ");
-                                        msg.remove(0);    
-                                        let msg = trim_trailing_whitespace(&msg);            
-                                        self.on_query(sender, address, &msg).await?;
-                                    }
-                                }
-                            },
-                            _ => {
-                                if let Ok(numeric) = parts[1].parse::<u16>() {
-                                    let padded_numeric = format!("{:03}", numeric);
+// secrets and the command line on top of what the file says, then checked
+fn finish_config(cli: &Cli, mut config: Config) -> Result<Config, Box<dyn Error>> {
+    if let Some(warning) = secrets::permission_warning(&cli.config, &config) {
+        report("alert", &warning);
+    }
+    config.load_secrets()?;
+    cli.apply(&mut config)?;
+    Ok(config.check(&cli.config)?)
+}
 
-                                    self.on_numeric(&padded_numeric, &line).await?;                                    
-                                } else {
-                                    self.on_unsupported(&line).await?;
-                                }
-                            }
-                        }
-                    }
+// tagged says to put the network's name on every printed line
+fn new_client(cli: &Cli, config: &BuzzenConfig, tagged: bool) -> Result<IrcClient, Box<dyn Error>> {
+    let mut client = IrcClient::new(config)?;
+    if cli.json {
+        // a subscriber instead of a handler, events are printed as they are broadcast
+        let mut events = client.subscribe();
+        tokio::spawn(async move {
+            loop {
+                match events.recv().await {
+                    Ok(envelope) => match serde_json::to_string(&envelope) {
+                        Ok(json) => println!("{}", json),
+                        Err(err) => eprintln!("{}", err),
+                    },
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
                 }
-                
-                self.message.replace_range(..pos + 1, "");
             }
+        });
+    } else {
+        let mut console = ConsoleHandler::new(&config.channel_list()).verbose(cli.verbose);
+        if tagged {
+            console = console.network(config.network_name());
         }
+        client.add_handler(console);
     }
+    Ok(client)
+}
 
-
-    async fn on_welcome(&mut self, channel: &str, message: &str) -> io::Result<()> {
-        let message = &strip_style(&message);
-        let text = &format!(">> Welcome message for {} : {}", channel, message);
-        printall("welcome", text);
-        Ok(())
-    }
-
-    async fn on_whisper(&mut self, nick: &str, address: &str, channel: &str, message: &str) -> io::Result<()> {
-        let message = &strip_style(&message);
-        let text = &format!(">> Query from {} ({}) in {} : {}", nick, address, channel, message);
-        printall("whisper", text);
-        Ok(())
-    }
-
-    async fn on_join(&mut self, nick: &str, address: &str, channel: &str) -> io::Result<()> {
-        let text = &format!(">> Join: {} ({}) has joined {}", nick, address, channel);
-        printall("join", text);
-        Ok(())
-    }
-
-    async fn on_part(&mut self, nick: &str, address: &str, channel: &str) -> io::Result<()> {
-        let text =  &format!(">> Part: {} ({}) has left {}", nick, address, channel);
-        printall("part", text);
-        Ok(())
-    }
-
-    async fn on_quit(&mut self, nick: &str, address: &str, reason: &str) -> io::Result<()> {
-        let text = &format!(">> Quit: {} ({}) has left the server. ({})", nick, address, reason);
-        printall("quit", text);
-        Ok(())
-    }
-
-    async fn on_nick(&mut self, nick: &str, address: &str, newnick: &str) -> io::Result<()> {
-        if nick == self.nickname { // keep track of your own nick change
-            self.nickname = newnick.to_string();
-        }
-        let text = &format!(">> Nick: {} ({}) has changed their nick to: {}", nick, address, newnick);
-        printall("nick", text);
-        Ok(())
-    }
-
-    // need to add support for actions and ctcp messages
-    async fn on_privmsg(&mut self, nick: &str, address: &str, channel: &str, message: &str) -> io::Result<()> {
-        let message = &strip_style(&message);
-        if message.starts_with('\u{0001}') && message.ends_with('\u{0001}') && message.len() > 1 {
-            let message = message.replace('\u{0001}', "");
-            let parts: Vec<&str> = message.split(' ').collect();
-            if parts[0].to_uppercase() == "ACTION" {
-                let action_message: String;
-                if parts.len() > 1 {
-                    action_message = parts[1..].join(" ");
-                } else {
-                    action_message = String::new(); // handle possible blank action
-                }
-                self.on_action(nick, address, channel, &action_message).await?
-            } else {
-                // CTCP Request
-                self.on_ctcp_request(nick, address, &message).await?
-            }            
-        } else {
-            // because buzzen is weird 
-            if message.starts_with('\u{0002}') && message.ends_with('\u{0002}') && message.len() > 1 {
-                let message = &message[1..message.len() - 1];
-                let parts: Vec<&str> = message.split(' ').collect();
-                let ctcp_type = parts[0].to_uppercase();
-                if parts.len() > 1 {
-                    let ctcp_reply = parts[1..].join(" ");
-                    self.on_ctcp_reply(nick, address, &ctcp_type, &ctcp_reply).await?
-                } else {
-                    self.on_ctcp_request(nick, address, &ctcp_type).await?
-                }
-            } else {
-                let text = &format!("{}: {}", nick, message);
-                printall("privmsg", &text);
+async fn run(cli: &Cli, config: Config) -> Result<(), Box<dyn Error>> {
+    // one client per network, each with its own reconnect supervisor
+    let several = config.networks.len() > 1;
+    let mut terminal = Terminal::new("");
+    let mut senders: Vec<(String, Sender)> = Vec::new();
+    let mut reloaders: Vec<(String, Reloader)> = Vec::new();
+    let mut servers = Vec::new();
+    for network in &config.networks {
+        let mut client = new_client(cli, network, several)?;
+        let name = network.network_name().to_string();
+        let channels = network.channel_list();
+        terminal.add_network(&name, channels.first().map_or("", |channel| channel.name.as_str()));
+        senders.push((name.clone(), client.sender()));
+        reloaders.push((name.clone(), client.reloader()));
+
+        let tag = if several { format!("[{}] ", name) } else { String::new() };
+        servers.push(tokio::spawn(async move {
+            // connects, logs in and reconnects until /quit
+            if let Err(err) = client.run().await {
+                report("alert", &format!("{}{}", tag, err));
             }
-        }
-        Ok(())
+        }));
     }
 
-    async fn on_action(&mut self, nick: &str, _addrress: &str, _channel: &str, message: &str) -> io::Result<()> {
-        let text = &format!("{} {}", nick, message);
-        printall("action", text);
-        Ok(())     
-    }
+    // /reload, SIGHUP and saving the config file all read it again
+    let (reload, reloads) = mpsc::unbounded_channel();
+    // the watcher stops when dropped, so it lives as long as run() does
+    let _watcher = match watch_file(&cli.config, reload.clone()) {
+        Ok(watcher) => Some(watcher),
+        Err(err) => {
+            report("alert", &format!("Not watching {} for changes: {}", cli.config, err));
+            None
+        },
+    };
+    watch_hangup(reload.clone());
+    tokio::spawn(reload_on_request(cli.clone(), reloads, reloaders));
 
-    // need to add support for actions and ctcp messages
-    async fn on_query(&mut self, nick: &str, address: &str, message: &str) -> io::Result<()> {
-        let message = &strip_style(&message);
-        if message.starts_with('\u{0001}') && message.ends_with('\u{0001}') && message.len() > 1 {
-            let message = &message[1..message.len() - 1];
-            let parts: Vec<&str> = message.split(' ').collect();
-            if parts[0].to_uppercase() == "ACTION" {
-                let action_message: String;
-                if parts.len() > 1 {
-                    action_message = parts[1..].join(" ");
-                } else {
-                    action_message = String::new(); // handle possible blank action
-                }
-                self.on_query_action(nick, address,  &action_message).await?
-            } else {
-                // CTCP Request
-                self.on_ctcp_request(nick, address, message).await?
+    let terminal = tokio::spawn(async move {
+        let mut lines = BufReader::new(tokio::io::stdin()).lines();
+        while let Ok(Some(input)) = lines.next_line().await {
+            match terminal.handle(&input) {
+                // /quit leaves every network, anything else goes to the one we talk on
+                Ok(Some(line)) if terminal.is_quitting() => {
+                    for (_, sender) in &senders {
+                        let _ = sender.send(&line);
+                    }
+                },
+                // Send the user input to the IRC server
+                Ok(Some(line)) => {
+                    let sender = senders.iter().find(|(name, _)| name == terminal.network()).map(|(_, sender)| sender);
+                    if sender.is_none_or(|sender| sender.send(&line).is_err()) {
+                        report("alert", &format!("Not connected to {}", terminal.network()));
+                    }
+                },
+                Ok(None) => {},
+                Err(err) => report("alert", &err),
             }
-        } else {
-            // because buzzen is weird
-            if message.starts_with('\u{0002}') && message.ends_with('\u{0002}') && message.len() > 1 {
-                let message = &message[1..message.len() - 1];
-                let parts: Vec<&str> = message.split(' ').collect();
-                let ctcp_type = parts[0].to_uppercase();
-                if parts.len() > 1 {
-                    let ctcp_reply = parts[1..].join(" ");
-                    self.on_ctcp_reply(nick, address, &ctcp_type, &ctcp_reply).await?
-                } else {
-                    self.on_ctcp_request(nick, address, &ctcp_type).await?
-                }
-            } else {
-                let text = &format!("{}: {}", nick, message);
-                printall("privmsg", &text);
+            if terminal.take_reload() {
+                let _ = reload.send(());
+            }
+            if terminal.is_quitting() {
+                break;
             }
         }
-        Ok(())
-    }
-
-    // not going to bother adding channel specific ctcp requests since the bot only sits in 1 channel 
-    async fn on_ctcp_request(&mut self, nick: &str, address: &str, request: &str) -> io::Result<()> {
-        let text = &format!(">> CTCP {} Request from {} ({})", request, nick, address);
-        printall("ctcprequest", text);
-        Ok(())
-    }
-
-    async fn on_query_action(&mut self, nick: &str, _address: &str, message: &str) -> io::Result<()> {
-        let message = &strip_style(&message);
-        let text = &format!(">> Query from {} : {}", nick, message);
-        printall("action", text);
-        Ok(())
-    }
-
-    async fn on_chanmode(&mut self, nick: &str, _address: &str, channel: &str, modes: &str) -> io::Result<()> {
-        let text = &format!(">> Mode: {} sets modes in {} to {}", nick, channel, modes);
-        printall("mode", text);
-        Ok(())
-    }
+    });
 
-    async fn on_usermode(&mut self, modes: &str) -> io::Result<()> {
-        let text = &format!(">> Usermode: {}", modes);
-        printall("usermode", text);
-        Ok(())
+    terminal.await?;
+    for server in servers {
+        server.await?;
     }
 
-    async fn on_kick(&mut self, nick: &str, address: &str, knick: &str, channel:&str, reason: &str) -> io::Result<()> {
-        let text = &format!(">> Kick: {} ({}) has kicked {} from {} : {}", nick, address, knick, channel, reason);
-        printall("kick", text);
-        Ok(())
-    }
+    Ok(())
+}
 
-    async fn on_channel_notice(&mut self, nick: &str, address: &str, channel: &str, message: &str) -> io::Result<()> {
-        let message = &strip_style(&message);
-        if message.starts_with('\u{0001}') && message.ends_with('\u{0001}') && message.len() > 1 {
-            let msg = &message[1..message.len() - 1];
-            let parts: Vec<&str> = msg.split(' ').collect();
-            let ctcp_type = parts[0];
-            if parts.len() == 1 {
-                self.on_ctcp_reply(nick, address, ctcp_type, "").await?
-            } else {
-                let ctcp_reply = parts[1..].join(" ");                
-                self.on_ctcp_reply(nick, address, ctcp_type, &ctcp_reply).await?
+// Networks are matched up by name. Adding or removing one takes a restart,
+// everything inside a network is reloaded live.
+async fn reload_on_request(cli: Cli, mut reloads: mpsc::UnboundedReceiver<()>, reloaders: Vec<(String, Reloader)>) {
+    while reloads.recv().await.is_some() {
+        // editors save in more than one step, wait for the last one
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        while reloads.try_recv().is_ok() {}
+        let config = match reload_config(&cli) {
+            Ok(config) => config,
+            Err(err) => {
+                report("alert", &format!("Config not reloaded: {}", err));
+                continue;
+            },
+        };
+        for (name, reloader) in &reloaders {
+            match config.network(name) {
+                Some(network) => {
+                    let _ = reloader.reload(network.clone());
+                },
+                None => report("alert", &format!("{} is gone from the config, restart to disconnect from it", name)),
             }
-        } else {
-            let text = &format!(">> Notice to {} from {} ({}): {}", channel, nick, address, message);
-            printall("notice", text);
         }
-        Ok(())
-    }
-
-    async fn on_ctcp_reply(&mut self, nick: &str, address: &str, ctcp_type: &str, ctcp_reply: &str) -> io::Result<()> {
-        let text = &format!(">> CTCP {} Reply from {} ({}) : {}", ctcp_type, nick, address, ctcp_reply);
-        printall("ctcpreply", text);
-        Ok(())
-    }
-
-    // need to add support for ctcp messages
-    async fn on_private_notice(&mut self, nick: &str, address: &str, message: &str) -> io::Result<()> {
-        let message = &strip_style(&message);
-        if message.starts_with('\u{0001}') && message.ends_with('\u{0001}') && message.len() > 1 {
-            let msg = &message[1..message.len() - 1];
-            let parts: Vec<&str> = msg.split(' ').collect();
-            let ctcp_type = parts[0];
-            if parts.len() == 1 {
-                self.on_ctcp_reply(nick, address, ctcp_type, "").await?
-            } else {
-                let ctcp_reply = parts[1..].join(" ");                
-                self.on_ctcp_reply(nick, address, ctcp_type, &ctcp_reply).await?
+        for network in &config.networks {
+            if !reloaders.iter().any(|(name, _)| name.eq_ignore_ascii_case(network.network_name())) {
+                report("alert", &format!("{} is new in the config, restart to connect to it", network.network_name()));
             }
-        } else {
-            let text = &format!(">> Notice from {} ({}): {}", nick, address, message);
-            printall("notice", text);
         }
-        Ok(())
     }
+}
 
-    async fn on_channel_snotice(&mut self, channel: &str, message: &str) -> io::Result<()> {
-        let message = &strip_style(&message);
-        if message.starts_with('\u{0001}') && message.ends_with('\u{0001}') && message.len() > 1 {
-            let msg = &message[1..message.len() - 1];
-            let parts: Vec<&str> = msg.split(' ').collect();
-            let ctcp_type = parts[0];
-            if parts.len() == 1 {
-                self.on_server_ctcp(ctcp_type, "").await?
-            } else {
-                let ctcp_reply = parts[1..].join(" ");                
-                self.on_server_ctcp(ctcp_type, &ctcp_reply).await?
-            }
-        } else {
-            let text = &format!(">> Notice to {} : {}", channel, message);
-            printall("snotice", &text);
+// Watches the directory rather than the file, editors often replace the file
+// instead of writing to it.
+fn watch_file(path: &str, reload: mpsc::UnboundedSender<()>) -> notify::Result<RecommendedWatcher> {
+    let path = fs::canonicalize(path)?;
+    let name = path.file_name().map(|name| name.to_os_string());
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let Ok(event) = event else {
+            return;
+        };
+        let ours = event.paths.iter().any(|changed| changed.file_name() == name.as_deref());
+        if ours && (event.kind.is_create() || event.kind.is_modify()) {
+            let _ = reload.send(());
         }
-        Ok(())
+    })?;
+    if let Some(dir) = path.parent() {
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
     }
+    Ok(watcher)
+}
 
-    async fn on_private_snotice(&mut self, message: &str) -> io::Result<()> {
-        let message = &strip_style(&message);
-        if message.starts_with('\u{0001}') && message.ends_with('\u{0001}') && message.len() > 1 {
-            let msg = &message[1..message.len() - 1];
-            let parts: Vec<&str> = msg.split(' ').collect();
-            let ctcp_type = parts[0];
-            if parts.len() == 1 {
-                self.on_server_ctcp(ctcp_type, "").await?
-            } else {
-                let ctcp_reply = parts[1..].join(" ");                
-                self.on_server_ctcp(ctcp_type, &ctcp_reply).await?
+#[cfg(unix)]
+fn watch_hangup(reload: mpsc::UnboundedSender<()>) {
+    use tokio::signal::unix::{signal, SignalKind};
+    tokio::spawn(async move {
+        let Ok(mut hangup) = signal(SignalKind::hangup()) else {
+            return;
+        };
+        while hangup.recv().await.is_some() {
+            if reload.send(()).is_err() {
+                break;
             }
-        } else {
-            let text = &format!(">> Notice: {}", message);
-            printall("snotice", text);
         }
-        Ok(())
-    }
-
-    async fn on_server_ctcp(&mut self, ctcp_type: &str, ctcp_reply: &str) -> io::Result<()> {
-        let text = &format!(">> CTCP {} from Server: {}", ctcp_type, ctcp_reply);
-        printall("sctcp", text);
-        Ok(())
-    }
+    });
+}
 
-    async fn on_numeric(&mut self, numeric: &str, line: &str) -> io::Result<()> {
-        // for now print all numerics and their messages
-        let parts: Vec<&str> = line.split(' ').collect();
-        let message_parts = &parts[3..];
-        let mut numeric_msg = message_parts.join(" ");
-        if numeric_msg.starts_with(':') {
-            numeric_msg.remove(0);
-        }
-        let text = &format!(">> Numeric({}): {}", numeric, numeric_msg);
-        match &numeric[..] {
-            "001" => {
-                /* Welcome to...  */ 
-                printall("numeric", text);
-                let parts: Vec<&str> = numeric_msg.split(' ').collect();
-                if parts.len() > 5 { 
-                    self.nickname = parts[5].split('!').next().unwrap().to_string();
-                    self.address = parts[5].split('!').nth(1).unwrap().to_string();
-                }
-                self.write(&format!("JOIN {}",self.channel)).await?;
-            },
-            /* 
-            "002" => { /* Your host is... */ } ,
-            "003" => { /* This server was created... */ } ,
-            "004" => { /* Server type version... */ } ,
-            "005" => { /* Server Supported Info */ } ,
-            "251" => { /* There are _ users and _ invisible on _ servers. */ } ,
-            "252" => { /* _ :operator(s) online */ } ,
-            "253" => { /* _ :unknown ocnnections */ } ,
-            "254" => { /* _ :channels formed */ } ,
-            "255" => { /* I have _ clients and _ servers */ } ,
-            "265" => { /* Current local users: _ Max: _ */ } ,
-            "266" => { /* Current global users: _ Max: _ */ } ,
-            "332" => { /* Channel Topic */ } ,
-            "333" => { /* Channel Topic set by and timestamp */ } ,
-            "353" => { /* Channel /NAMES LIST */ } ,
-            "366" => { /* End of /NAMES LIST */ } ,
-            "375" => { /* START OF MOTD */ } ,
-            "372" => { /* MOTD */ } ,
-            "376" => { /* END OF MOTD */ } ,
-            */
-            "821" => { /* :UNAWAY MESSAGE */
-                // :<NICK!USER@ADDRESS> 821 <CHANNEL> :<MESSAGE>
-                let sender = parts[0].split('!').next().unwrap();
-                let sender = &sender[1..];
-                let message = &strip_style(&numeric_msg);
-                let message = trim_trailing_whitespace(&message);
-                // let address = parts[0].split('!').nth(1).unwrap();
-                let text = format!(">> Back: {} has returned! ({})", sender, message);
-                printall("unaway", &text)
-            },
-            "822" => { /* :AWAY MESSAGE */
-                // :<NICK!USER@ADDRESS> 822 <CHANNEL> :<MESSAGE>
-                let sender = parts[0].split('!').next().unwrap();
-                let sender = &sender[1..];
-                let message = &strip_style(&numeric_msg);
-                let message = trim_trailing_whitespace(&message);
-                let text = format!(">> Away: {} has gone away. ({})", sender, message);
-                printall("away", &text)
-            },
-            _ => {
-                // For more information on numerics: https://datatracker.ietf.org/doc/html/rfc2812
-                printall("numeric", text);
+#[cfg(not(unix))]
+fn watch_hangup(_reload: mpsc::UnboundedSender<()>) {}
+
+// Log in, join target if it is a channel, say text and quit.
+async fn send(cli: &Cli, mut config: BuzzenConfig, target: &str, text: &str) -> Result<(), Box<dyn Error>> {
+    // only the channel we talk in, keeping its key if it has one
+    let configured = config.channel_list();
+    config.channels = match is_channel(target) {
+        true => vec![channels::find(&configured, target).cloned().unwrap_or_else(|| ChannelConfig::new(target))],
+        false => Vec::new(),
+    };
+    config.channel.clear();
+
+    let mut client = new_client(cli, &config, false)?;
+    let sender = client.sender();
+    let mut events = client.subscribe();
+    let server = tokio::spawn(async move { client.run().await });
+
+    let ready = async {
+        loop {
+            match events.recv().await {
+                Ok(envelope) => match envelope.event {
+                    Event::Join { channel, .. } if channel.eq_ignore_ascii_case(target) => return true,
+                    Event::Numeric { numeric, .. } if numeric == "001" && !is_channel(target) => return true,
+                    _ => {},
+                },
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return false,
             }
         }
-        Ok(())
-    } 
-
-    async fn on_unsupported(&mut self, line: &str) -> io::Result<()> {
-        let text = &format!("Unsupported event: {}", line); // print anything i have not added/forgot
-        printall("default", text);
-        Ok(())
+    };
+    match tokio::time::timeout(SEND_TIMEOUT, ready).await {
+        Ok(true) => {
+            sender.privmsg(target, text)?;
+            sender.send("QUIT")?;
+            server.await??;
+            Ok(())
+        },
+        // the client gave up on its own, its error says why
+        Ok(false) => {
+            server.await??;
+            Err("Connection closed before the message was sent".into())
+        },
+        Err(_) => {
+            server.abort();
+            Err(format!("Gave up after {} seconds without getting into {}", SEND_TIMEOUT.as_secs(), target).into())
+        },
     }
 }
-
-fn strip_style(value: &str) -> String {
-    // strip Buzzen [style] tags
-    let style_regex = Regex::new(r"\[(?:/)?style(?:[^\]]+)?\]").unwrap();
-    let result = style_regex.replace_all(value, "");
-
-    // strip mIRC codes (underline, bold, color, ect...)
-    let special_regex = Regex::new(r"(\u{0003}(\d(\d)?(,(\d(\d)?)?)?)?|\u{001F}|\u{0002}|\u{000F}|\u{0016})").unwrap();
-    special_regex.replace_all(&result, "").to_string()
-}
-
-fn trim_trailing_whitespace(input: &str) -> String {
-    input.chars()
-        .rev() // Start from the end of the string
-        .skip_while(|&c| c.is_whitespace()) // Skip whitespaces
-        .collect::<String>() // Collect the characters into a string
-        .chars() // Reversed, so reverse it again to get original order
-        .rev()
-        .collect::<String>() // Collect the characters into a string
-}
-
-// 
\ No newline at end of file
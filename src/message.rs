@@ -0,0 +1,306 @@
+// A single line received from the server, split into its parts.
+//
+//   [@tags] [:prefix] COMMAND [middle params...] [:trailing param]
+//
+// Buzzen sends a few commands with an extra middle parameter compared to
+// RFC 2812 (see Event::from_message), so nothing here assumes a fixed
+// number of params. Callers should look at `params` and `trailing` instead
+// of counting spaces.
+
+use std::{error::Error, fmt};
+
+use crate::tags::Tags;
+
+// A line the server sent that can't be turned into an IrcMessage.
+// The connection stays up; the line is reported through on_parse_error.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub line: String,
+    pub reason: String,
+}
+
+impl ParseError {
+    pub fn new(line: &str, reason: &str) -> Self {
+        ParseError { line: line.to_string(), reason: reason.to_string() }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.reason, self.line)
+    }
+}
+
+impl Error for ParseError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Prefix {
+    pub nick: String,
+    pub user: Option<String>,
+    pub host: Option<String>,
+}
+
+impl Prefix {
+    pub fn parse(value: &str) -> Self {
+        // :<NICK!USER@ADDRESS> or :<SERVER>
+        let (nick, rest) = match value.split_once('!') {
+            Some((nick, rest)) => (nick, Some(rest)),
+            None => (value, None),
+        };
+        let (user, host) = match rest {
+            Some(rest) => match rest.split_once('@') {
+                Some((user, host)) => (Some(user.to_string()), Some(host.to_string())),
+                None => (Some(rest.to_string()), None),
+            },
+            None => match nick.split_once('@') {
+                Some((_, host)) => (None, Some(host.to_string())),
+                None => (None, None),
+            },
+        };
+        let nick = nick.split('@').next().unwrap_or(nick).to_string();
+        Prefix { nick, user, host }
+    }
+
+    // USER@ADDRESS part of the prefix, empty for server prefixes
+    pub fn address(&self) -> String {
+        match (&self.user, &self.host) {
+            (Some(user), Some(host)) => format!("{}@{}", user, host),
+            (Some(user), None) => user.to_string(),
+            (None, Some(host)) => host.to_string(),
+            (None, None) => String::new(),
+        }
+    }
+
+    // servers and Buzzen channel prefixes (%#Channel) have no user part
+    pub fn is_user(&self) -> bool {
+        self.user.is_some()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IrcMessage {
+    pub tags: Tags,
+    pub prefix: Option<Prefix>,
+    pub command: String,
+    pub params: Vec<String>,
+    pub trailing: Option<String>,
+}
+
+impl IrcMessage {
+    pub fn parse(line: &str) -> Result<Self, ParseError> {
+        let line = line.trim_end_matches(['\r', '\n']);
+        let mut rest = line;
+
+        let mut tags = Tags::new();
+        if let Some(stripped) = rest.strip_prefix('@') {
+            let (raw_tags, remainder) = stripped.split_once(' ').unwrap_or((stripped, ""));
+            tags = Tags::parse(raw_tags);
+            rest = remainder;
+        }
+
+        rest = rest.trim_start_matches(' ');
+        let mut prefix = None;
+        if let Some(stripped) = rest.strip_prefix(':') {
+            let (raw_prefix, remainder) = stripped.split_once(' ').unwrap_or((stripped, ""));
+            prefix = Some(Prefix::parse(raw_prefix));
+            rest = remainder;
+        }
+
+        rest = rest.trim_start_matches(' ');
+        let (command, remainder) = rest.split_once(' ').unwrap_or((rest, ""));
+        if command.is_empty() {
+            return Err(ParseError::new(line, "missing command"));
+        }
+        let command = command.to_uppercase();
+        rest = remainder;
+
+        let mut params = Vec::new();
+        let mut trailing = None;
+        loop {
+            rest = rest.trim_start_matches(' ');
+            if rest.is_empty() {
+                break;
+            }
+            if let Some(stripped) = rest.strip_prefix(':') {
+                trailing = Some(stripped.to_string());
+                break;
+            }
+            let (param, remainder) = rest.split_once(' ').unwrap_or((rest, ""));
+            params.push(param.to_string());
+            rest = remainder;
+        }
+
+        let message = IrcMessage { tags, prefix, command, params, trailing };
+        message.check_params(line)?;
+        Ok(message)
+    }
+
+    // Make sure every param dispatch indexes into is actually there, so
+    // a short line from the server can't take the client down.
+    fn check_params(&self, line: &str) -> Result<(), ParseError> {
+        let (needed, reason) = match self.command.as_str() {
+            // KICK <CHANNEL> <TARGET> and WHISPER <CHANNEL> <TARGET>
+            "KICK" => (2, "KICK without a channel and target"),
+            "WHISPER" => (2, "WHISPER without a channel and target"),
+            "MODE" => (1, "MODE without a target"),
+            "NOTICE" => (1, "NOTICE without a target"),
+            "PRIVMSG" => (1, "PRIVMSG without a target"),
+            "JOIN" if self.text().is_empty() => return Err(ParseError::new(line, "JOIN without a channel")),
+            // some servers send the channel as the trailing param: PART :#chan
+            "PART" if self.text().is_empty() => return Err(ParseError::new(line, "PART without a channel")),
+            "NICK" if self.text().is_empty() => return Err(ParseError::new(line, "NICK without a new nickname")),
+            _ => (0, ""),
+        };
+        if self.params.len() < needed {
+            return Err(ParseError::new(line, reason));
+        }
+        if needed > 0 && self.prefix.is_none() && self.command != "NOTICE" {
+            return Err(ParseError::new(line, &format!("{} without a prefix", self.command)));
+        }
+        Ok(())
+    }
+
+    pub fn param(&self, index: usize) -> Option<&str> {
+        self.params.get(index).map(|param| param.as_str())
+    }
+
+    pub fn nick(&self) -> &str {
+        self.prefix.as_ref().map(|prefix| prefix.nick.as_str()).unwrap_or("")
+    }
+
+    pub fn address(&self) -> String {
+        self.prefix.as_ref().map(|prefix| prefix.address()).unwrap_or_default()
+    }
+
+    // the trailing param, or the last middle param when the server left off the ':'
+    pub fn text(&self) -> &str {
+        match &self.trailing {
+            Some(trailing) => trailing,
+            None => self.params.last().map(|param| param.as_str()).unwrap_or(""),
+        }
+    }
+
+    // every param from `start` on joined back together, trailing included
+    pub fn text_from(&self, start: usize) -> String {
+        let mut parts: Vec<&str> = self.params.iter().skip(start).map(|param| param.as_str()).collect();
+        if let Some(trailing) = &self.trailing {
+            parts.push(trailing);
+        }
+        parts.join(" ")
+    }
+
+    pub fn numeric(&self) -> Option<u16> {
+        if self.command.len() == 3 {
+            self.command.parse::<u16>().ok()
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_prefix_params_and_trailing() {
+        let msg = IrcMessage::parse(":nick!user@host PRIVMSG #rust :hello there\r\n").unwrap();
+        assert_eq!(msg.prefix, Some(Prefix { nick: "nick".into(), user: Some("user".into()), host: Some("host".into()) }));
+        assert_eq!(msg.command, "PRIVMSG");
+        assert_eq!(msg.params, vec!["#rust"]);
+        assert_eq!(msg.trailing.as_deref(), Some("hello there"));
+        assert_eq!(msg.text(), "hello there");
+        assert_eq!(msg.address(), "user@host");
+    }
+
+    #[test]
+    fn parses_tags_prefix_and_trailing() {
+        let msg = IrcMessage::parse("@msgid=abc;time=2024-01-01T00:00:00.000Z;+draft/reply=x\\sy :nick!u@h PRIVMSG #rust :hi there").unwrap();
+        assert_eq!(msg.tags.msgid(), Some("abc"));
+        assert_eq!(msg.tags.get("time"), Some("2024-01-01T00:00:00.000Z"));
+        assert_eq!(msg.tags.get("+draft/reply"), Some("x y"));
+        assert_eq!(msg.nick(), "nick");
+        assert_eq!(msg.params, vec!["#rust"]);
+        assert_eq!(msg.text(), "hi there");
+    }
+
+    #[test]
+    fn server_prefix_and_numeric() {
+        let msg = IrcMessage::parse(":irc.example.net 001 bot :Welcome bot!u@h").unwrap();
+        assert_eq!(msg.nick(), "irc.example.net");
+        assert!(!msg.prefix.as_ref().unwrap().is_user());
+        assert_eq!(msg.numeric(), Some(1));
+        assert_eq!(msg.param(0), Some("bot"));
+    }
+
+    #[test]
+    fn collapses_repeated_spaces() {
+        let msg = IrcMessage::parse(":nick!u@h  KICK   #chan  victim  :go away").unwrap();
+        assert_eq!(msg.command, "KICK");
+        assert_eq!(msg.params, vec!["#chan", "victim"]);
+        assert_eq!(msg.text(), "go away");
+        assert_eq!(msg.text_from(1), "victim go away");
+    }
+
+    #[test]
+    fn text_without_colon_is_the_last_param() {
+        let msg = IrcMessage::parse(":nick!u@h NICK newnick").unwrap();
+        assert_eq!(msg.trailing, None);
+        assert_eq!(msg.text(), "newnick");
+    }
+
+    #[test]
+    fn buzzen_join_with_profile() {
+        // Buzzen puts profile data between JOIN and the channel
+        let msg = IrcMessage::parse(":nick!u@h JOIN 0,1,2,x :%#Lobby").unwrap();
+        assert_eq!(msg.params, vec!["0,1,2,x"]);
+        assert_eq!(msg.text(), "%#Lobby");
+    }
+
+    #[test]
+    fn missing_command_is_an_error() {
+        for line in ["", "   ", ":nick!u@h", ":nick!u@h   "] {
+            let err = IrcMessage::parse(line).unwrap_err();
+            assert_eq!(err.reason, "missing command", "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn short_lines_are_errors() {
+        let cases = [
+            (":nick!u@h KICK #chan", "KICK without a channel and target"),
+            (":nick!u@h KICK", "KICK without a channel and target"),
+            (":nick!u@h WHISPER #chan :hi", "WHISPER without a channel and target"),
+            (":nick!u@h PRIVMSG :hi", "PRIVMSG without a target"),
+            (":nick!u@h MODE", "MODE without a target"),
+            (":nick!u@h JOIN", "JOIN without a channel"),
+            (":nick!u@h PART", "PART without a channel"),
+            (":nick!u@h NICK", "NICK without a new nickname"),
+            ("KICK #chan victim", "KICK without a prefix"),
+        ];
+        for (line, reason) in cases {
+            let err = IrcMessage::parse(line).unwrap_err();
+            assert_eq!(err.reason, reason, "{:?}", line);
+            assert_eq!(err.line, line);
+        }
+    }
+
+    #[test]
+    fn part_with_the_channel_as_trailing() {
+        let msg = IrcMessage::parse(":nick!u@h PART :#chan").unwrap();
+        assert_eq!(msg.param(0).unwrap_or(msg.text()), "#chan");
+        let msg = IrcMessage::parse(":nick!u@h PART #chan :bye").unwrap();
+        assert_eq!(msg.param(0).unwrap_or(msg.text()), "#chan");
+    }
+
+    #[test]
+    fn server_notice_needs_no_prefix() {
+        let msg = IrcMessage::parse("NOTICE * :*** Looking up your hostname").unwrap();
+        assert_eq!(msg.param(0), Some("*"));
+    }
+
+    #[test]
+    fn lowercase_commands_are_uppercased() {
+        assert_eq!(IrcMessage::parse("ping :token").unwrap().command, "PING");
+    }
+}
@@ -0,0 +1,54 @@
+// A fake IRC server on the other end of an in-memory stream, for tests that
+// drive a whole IrcClient.
+
+use tokio::io::{duplex, AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream, Lines, ReadHalf, WriteHalf};
+use tokio::task::JoinHandle;
+
+use crate::config::BuzzenConfig;
+use crate::event::Event;
+use crate::login::Network;
+use crate::IrcClient;
+
+pub(crate) struct MockServer {
+    lines: Lines<BufReader<ReadHalf<DuplexStream>>>,
+    writer: WriteHalf<DuplexStream>,
+}
+
+impl MockServer {
+    // the next line the client sent should be expected
+    pub(crate) async fn expect(&mut self, expected: &str) {
+        let line = self.lines.next_line().await.unwrap().unwrap();
+        assert_eq!(line, expected);
+    }
+
+    pub(crate) async fn send(&mut self, line: &str) {
+        self.writer.write_all(format!("{}\r\n", line).as_bytes()).await.unwrap();
+    }
+}
+
+// an RFC 2812 login as "bot" without rate limiting
+pub(crate) fn config() -> BuzzenConfig {
+    let mut config = BuzzenConfig { nickname: "bot".to_string(), network: Network::Irc, ..Default::default() };
+    config.rate_limit.refill_per_second = 0.0;
+    config
+}
+
+// Attach the client to a new MockServer and run it until the server hangs
+// up. The task returns every event the client emitted.
+pub(crate) async fn connect(mut client: IrcClient) -> (MockServer, JoinHandle<Vec<Event>>) {
+    let mut events = client.subscribe();
+    let (stream, server) = duplex(4096);
+    client.attach(stream).await.unwrap();
+    let client = tokio::spawn(async move {
+        let _ = client.process_messages().await;
+        drop(client);
+        let mut seen = Vec::new();
+        while let Ok(envelope) = events.recv().await {
+            seen.push(envelope.event);
+        }
+        seen
+    });
+
+    let (reader, writer) = tokio::io::split(server);
+    (MockServer { lines: BufReader::new(reader).lines(), writer }, client)
+}
@@ -0,0 +1,367 @@
+// Tunnels the connection through a SOCKS5 or HTTP CONNECT proxy, for hosts
+// that can't reach the IRC server directly. Once handshake() returns, the
+// stream behaves as if it was connected to the server itself, so TLS and the
+// IRC login run on top of it unchanged.
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::net::IpAddr;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProxyKind {
+    Socks5,
+    Http,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProxyConfig {
+    #[serde(rename = "type")]
+    pub kind: ProxyKind,
+    pub host: String,
+    pub port: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+}
+
+impl ProxyConfig {
+    pub fn address(&self) -> String {
+        if self.host.contains(':') {
+            format!("[{}]:{}", self.host, self.port)
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+}
+
+// Ask the proxy on the other end of stream to connect us to host:port.
+pub async fn handshake<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S, proxy: &ProxyConfig, host: &str, port: u16) -> io::Result<()> {
+    match proxy.kind {
+        ProxyKind::Socks5 => socks5(stream, proxy, host, port).await,
+        ProxyKind::Http => http_connect(stream, proxy, host, port).await,
+    }
+}
+
+// RFC 1928, with username/password auth from RFC 1929
+async fn socks5<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S, proxy: &ProxyConfig, host: &str, port: u16) -> io::Result<()> {
+    let credentials = proxy.username.as_deref().map(|user| (user, proxy.password.as_deref().unwrap_or("")));
+    if credentials.is_some() {
+        stream.write_all(&[5, 2, 0, 2]).await?;
+    } else {
+        stream.write_all(&[5, 1, 0]).await?;
+    }
+
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != 5 {
+        return Err(proxy_error("SOCKS5 proxy sent an invalid reply"));
+    }
+    match (reply[1], credentials) {
+        (0, _) => {},
+        (2, Some((user, pass))) => {
+            if user.len() > 255 || pass.len() > 255 {
+                return Err(proxy_error("SOCKS5 username and password can be at most 255 bytes"));
+            }
+            let mut auth = vec![1, user.len() as u8];
+            auth.extend_from_slice(user.as_bytes());
+            auth.push(pass.len() as u8);
+            auth.extend_from_slice(pass.as_bytes());
+            stream.write_all(&auth).await?;
+
+            stream.read_exact(&mut reply).await?;
+            if reply[1] != 0 {
+                return Err(proxy_error("SOCKS5 proxy rejected the username or password"));
+            }
+        },
+        _ => return Err(proxy_error("SOCKS5 proxy accepted none of our authentication methods")),
+    }
+
+    let mut request = vec![5, 1, 0];
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            request.push(1);
+            request.extend_from_slice(&ip.octets());
+        },
+        Ok(IpAddr::V6(ip)) => {
+            request.push(4);
+            request.extend_from_slice(&ip.octets());
+        },
+        // let the proxy resolve the name, the host may not be able to
+        Err(_) => {
+            if host.len() > 255 {
+                return Err(proxy_error("Server name is too long for SOCKS5"));
+            }
+            request.push(3);
+            request.push(host.len() as u8);
+            request.extend_from_slice(host.as_bytes());
+        },
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != 5 {
+        return Err(proxy_error("SOCKS5 proxy sent an invalid reply"));
+    }
+    if reply[1] != 0 {
+        let reason = match reply[1] {
+            1 => "general failure",
+            2 => "connection not allowed by ruleset",
+            3 => "network unreachable",
+            4 => "host unreachable",
+            5 => "connection refused",
+            6 => "TTL expired",
+            7 => "command not supported",
+            8 => "address type not supported",
+            _ => "unknown error",
+        };
+        return Err(proxy_error(&format!("SOCKS5 proxy could not connect to {}:{}: {}", host, port, reason)));
+    }
+    // skip the address the proxy bound to, nothing uses it
+    let length = match reply[3] {
+        1 => 4,
+        4 => 16,
+        3 => stream.read_u8().await? as usize,
+        _ => return Err(proxy_error("SOCKS5 proxy sent an invalid reply")),
+    };
+    let mut bound = vec![0u8; length + 2];
+    stream.read_exact(&mut bound).await?;
+    Ok(())
+}
+
+async fn http_connect<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S, proxy: &ProxyConfig, host: &str, port: u16) -> io::Result<()> {
+    let target = if host.contains(':') { format!("[{}]:{}", host, port) } else { format!("{}:{}", host, port) };
+    let mut request = format!("CONNECT {} HTTP/1.1\r\nHost: {}\r\n", target, target);
+    if let Some(user) = &proxy.username {
+        let credentials = format!("{}:{}", user, proxy.password.as_deref().unwrap_or(""));
+        request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", STANDARD.encode(credentials)));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await?;
+
+    // one byte at a time, so nothing the server sends after the headers is lost
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() > 8192 {
+            return Err(proxy_error("HTTP proxy response headers are too long"));
+        }
+        response.push(stream.read_u8().await?);
+    }
+
+    let response = String::from_utf8_lossy(&response);
+    let status_line = response.lines().next().unwrap_or("");
+    let status = status_line.split(' ').nth(1).unwrap_or("");
+    if !status.starts_with('2') {
+        return Err(proxy_error(&format!("HTTP proxy could not connect to {}: {}", target, status_line)));
+    }
+    Ok(())
+}
+
+fn proxy_error(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionRefused, reason.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{duplex, DuplexStream};
+
+    fn config(kind: ProxyKind, credentials: Option<(&str, &str)>) -> ProxyConfig {
+        ProxyConfig {
+            kind,
+            host: "proxy".to_string(),
+            port: 1080,
+            username: credentials.map(|(user, _)| user.to_string()),
+            password: credentials.map(|(_, pass)| pass.to_string()),
+        }
+    }
+
+    async fn expect(stream: &mut DuplexStream, expected: &[u8]) {
+        let mut received = vec![0u8; expected.len()];
+        stream.read_exact(&mut received).await.unwrap();
+        assert_eq!(received, expected);
+    }
+
+    // Run the handshake against a stub proxy, then read what the stub sent
+    // after its reply to make sure the handshake didn't eat any of it.
+    async fn run<F>(proxy: ProxyConfig, host: &str, port: u16, stub: impl FnOnce(DuplexStream) -> F) -> io::Result<String>
+    where
+        F: std::future::Future<Output = ()> + Send + 'static,
+    {
+        let (mut client, server) = duplex(16 * 1024);
+        let stub = tokio::spawn(stub(server));
+        let result = handshake(&mut client, &proxy, host, port).await;
+        stub.await.unwrap();
+        result?;
+        let mut rest = String::new();
+        client.read_to_string(&mut rest).await?;
+        Ok(rest)
+    }
+
+    #[tokio::test]
+    async fn socks5_domain_without_auth() {
+        let rest = run(config(ProxyKind::Socks5, None), "irc.example.net", 6667, |mut proxy| async move {
+            expect(&mut proxy, &[5, 1, 0]).await;
+            proxy.write_all(&[5, 0]).await.unwrap();
+            let mut request = vec![5, 1, 0, 3, 15];
+            request.extend_from_slice(b"irc.example.net");
+            request.extend_from_slice(&6667u16.to_be_bytes());
+            expect(&mut proxy, &request).await;
+            proxy.write_all(&[5, 0, 0, 1, 10, 0, 0, 1, 0x1f, 0x90]).await.unwrap();
+            proxy.write_all(b":server NOTICE * :hello\r\n").await.unwrap();
+        })
+        .await
+        .unwrap();
+        assert_eq!(rest, ":server NOTICE * :hello\r\n");
+    }
+
+    #[tokio::test]
+    async fn socks5_ipv4_with_auth() {
+        let rest = run(config(ProxyKind::Socks5, Some(("user", "hunter2"))), "127.0.0.1", 6697, |mut proxy| async move {
+            expect(&mut proxy, &[5, 2, 0, 2]).await;
+            proxy.write_all(&[5, 2]).await.unwrap();
+            let mut auth = vec![1, 4];
+            auth.extend_from_slice(b"user");
+            auth.push(7);
+            auth.extend_from_slice(b"hunter2");
+            expect(&mut proxy, &auth).await;
+            proxy.write_all(&[1, 0]).await.unwrap();
+            expect(&mut proxy, &[5, 1, 0, 1, 127, 0, 0, 1, 0x1a, 0x29]).await;
+            // bound to a domain name this time
+            proxy.write_all(&[5, 0, 0, 3, 5]).await.unwrap();
+            proxy.write_all(b"proxy\x00\x50after").await.unwrap();
+        })
+        .await
+        .unwrap();
+        assert_eq!(rest, "after");
+    }
+
+    #[tokio::test]
+    async fn socks5_ipv6_target() {
+        let rest = run(config(ProxyKind::Socks5, None), "::1", 6667, |mut proxy| async move {
+            expect(&mut proxy, &[5, 1, 0]).await;
+            proxy.write_all(&[5, 0]).await.unwrap();
+            let mut request = vec![5, 1, 0, 4];
+            request.extend_from_slice(&"::1".parse::<std::net::Ipv6Addr>().unwrap().octets());
+            request.extend_from_slice(&6667u16.to_be_bytes());
+            expect(&mut proxy, &request).await;
+            let mut reply = vec![5, 0, 0, 4];
+            reply.extend_from_slice(&[0; 18]);
+            proxy.write_all(&reply).await.unwrap();
+        })
+        .await
+        .unwrap();
+        assert_eq!(rest, "");
+    }
+
+    #[tokio::test]
+    async fn socks5_rejected_password() {
+        let err = run(config(ProxyKind::Socks5, Some(("user", "wrong"))), "irc.example.net", 6667, |mut proxy| async move {
+            expect(&mut proxy, &[5, 2, 0, 2]).await;
+            proxy.write_all(&[5, 2]).await.unwrap();
+            let mut auth = vec![0u8; 2 + 4 + 1 + 5];
+            proxy.read_exact(&mut auth).await.unwrap();
+            proxy.write_all(&[1, 1]).await.unwrap();
+        })
+        .await
+        .unwrap_err();
+        assert_eq!(err.to_string(), "SOCKS5 proxy rejected the username or password");
+    }
+
+    #[tokio::test]
+    async fn socks5_no_acceptable_method() {
+        let err = run(config(ProxyKind::Socks5, None), "irc.example.net", 6667, |mut proxy| async move {
+            expect(&mut proxy, &[5, 1, 0]).await;
+            proxy.write_all(&[5, 0xff]).await.unwrap();
+        })
+        .await
+        .unwrap_err();
+        assert_eq!(err.to_string(), "SOCKS5 proxy accepted none of our authentication methods");
+    }
+
+    #[tokio::test]
+    async fn socks5_error_replies() {
+        for (code, reason) in [(1, "general failure"), (2, "connection not allowed by ruleset"), (5, "connection refused"), (42, "unknown error")] {
+            let err = run(config(ProxyKind::Socks5, None), "irc.example.net", 6667, move |mut proxy| async move {
+                expect(&mut proxy, &[5, 1, 0]).await;
+                proxy.write_all(&[5, 0]).await.unwrap();
+                let mut request = vec![0u8; 5 + 15 + 2];
+                proxy.read_exact(&mut request).await.unwrap();
+                proxy.write_all(&[5, code, 0, 1]).await.unwrap();
+            })
+            .await
+            .unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+            assert_eq!(err.to_string(), format!("SOCKS5 proxy could not connect to irc.example.net:6667: {}", reason));
+        }
+    }
+
+    async fn read_request(proxy: &mut DuplexStream) -> String {
+        let mut request = Vec::new();
+        while !request.ends_with(b"\r\n\r\n") {
+            request.push(proxy.read_u8().await.unwrap());
+        }
+        String::from_utf8(request).unwrap()
+    }
+
+    #[tokio::test]
+    async fn http_connect_ok() {
+        let rest = run(config(ProxyKind::Http, Some(("user", "pass"))), "irc.example.net", 6697, |mut proxy| async move {
+            let request = read_request(&mut proxy).await;
+            assert_eq!(
+                request,
+                "CONNECT irc.example.net:6697 HTTP/1.1\r\nHost: irc.example.net:6697\r\nProxy-Authorization: Basic dXNlcjpwYXNz\r\n\r\n"
+            );
+            proxy.write_all(b"HTTP/1.1 200 Connection established\r\nVia: stub\r\n\r\n:server NOTICE * :hi\r\n").await.unwrap();
+        })
+        .await
+        .unwrap();
+        assert_eq!(rest, ":server NOTICE * :hi\r\n");
+    }
+
+    #[tokio::test]
+    async fn http_connect_ipv6_target() {
+        run(config(ProxyKind::Http, None), "::1", 6667, |mut proxy| async move {
+            assert_eq!(read_request(&mut proxy).await, "CONNECT [::1]:6667 HTTP/1.1\r\nHost: [::1]:6667\r\n\r\n");
+            proxy.write_all(b"HTTP/1.0 200 OK\r\n\r\n").await.unwrap();
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn http_connect_needs_auth() {
+        let err = run(config(ProxyKind::Http, None), "irc.example.net", 6667, |mut proxy| async move {
+            read_request(&mut proxy).await;
+            proxy.write_all(b"HTTP/1.1 407 Proxy Authentication Required\r\nProxy-Authenticate: Basic\r\n\r\n").await.unwrap();
+        })
+        .await
+        .unwrap_err();
+        assert_eq!(err.to_string(), "HTTP proxy could not connect to irc.example.net:6667: HTTP/1.1 407 Proxy Authentication Required");
+    }
+
+    #[tokio::test]
+    async fn http_connect_oversized_headers() {
+        let err = run(config(ProxyKind::Http, None), "irc.example.net", 6667, |mut proxy| async move {
+            read_request(&mut proxy).await;
+            proxy.write_all(b"HTTP/1.1 200 OK\r\n").await.unwrap();
+            // the client stops reading partway, so the write may fail
+            let _ = proxy.write_all(format!("X-Padding: {}\r\n", "x".repeat(10_000)).as_bytes()).await;
+        })
+        .await
+        .unwrap_err();
+        assert_eq!(err.to_string(), "HTTP proxy response headers are too long");
+    }
+
+    #[test]
+    fn ipv6_address_is_bracketed() {
+        let proxy = ProxyConfig { host: "::1".to_string(), ..config(ProxyKind::Socks5, None) };
+        assert_eq!(proxy.address(), "[::1]:1080");
+        assert_eq!(config(ProxyKind::Socks5, None).address(), "proxy:1080");
+    }
+}
@@ -0,0 +1,123 @@
+// Token bucket for outgoing lines, so pasting text or answering a burst of
+// CTCPs doesn't get us disconnected for excess flood.
+//
+// The bucket holds up to `burst` tokens and refills at `refill_per_second`.
+// Every line costs one token plus `per_byte_penalty` for each byte in it.
+
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::time::Instant;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimit {
+    pub burst: f64,
+    // 0 turns the limiter off
+    pub refill_per_second: f64,
+    pub per_byte_penalty: f64,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        RateLimit { burst: 5.0, refill_per_second: 0.5, per_byte_penalty: 0.002 }
+    }
+}
+
+pub struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(limit: RateLimit) -> Self {
+        let tokens = limit.burst;
+        TokenBucket { limit, tokens, updated: Instant::now() }
+    }
+
+    pub fn cost(&self, line: &str) -> f64 {
+        1.0 + line.len() as f64 * self.limit.per_byte_penalty
+    }
+
+    // How long until a line costing `cost` can be sent; zero means now.
+    pub fn wait_time(&mut self, cost: f64) -> Duration {
+        if self.limit.refill_per_second <= 0.0 {
+            return Duration::ZERO;
+        }
+        self.refill();
+        // a line that costs more than the whole bucket only waits for a full one
+        let cost = cost.min(self.limit.burst);
+        if self.tokens >= cost {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((cost - self.tokens) / self.limit.refill_per_second)
+        }
+    }
+
+    pub fn take(&mut self, cost: f64) {
+        if self.limit.refill_per_second <= 0.0 {
+            return;
+        }
+        self.refill();
+        self.tokens -= cost.min(self.limit.burst);
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.refill_per_second).min(self.limit.burst);
+        self.updated = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limit() -> RateLimit {
+        RateLimit { burst: 3.0, refill_per_second: 1.0, per_byte_penalty: 0.0 }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn burst_then_wait() {
+        let mut bucket = TokenBucket::new(limit());
+        for _ in 0..3 {
+            assert_eq!(bucket.wait_time(1.0), Duration::ZERO);
+            bucket.take(1.0);
+        }
+        assert_eq!(bucket.wait_time(1.0), Duration::from_secs(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn refills_over_time_up_to_burst() {
+        let mut bucket = TokenBucket::new(limit());
+        bucket.take(3.0);
+        tokio::time::advance(Duration::from_millis(1500)).await;
+        assert_eq!(bucket.wait_time(1.0), Duration::ZERO);
+        assert_eq!(bucket.wait_time(2.0), Duration::from_millis(500));
+
+        // a long quiet spell doesn't save up more than burst
+        tokio::time::advance(Duration::from_secs(60)).await;
+        bucket.take(3.0);
+        assert_eq!(bucket.wait_time(1.0), Duration::from_secs(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn oversized_line_waits_for_a_full_bucket() {
+        let mut bucket = TokenBucket::new(RateLimit { per_byte_penalty: 0.1, ..limit() });
+        let cost = bucket.cost(&"x".repeat(100));
+        assert_eq!(cost, 11.0);
+        assert_eq!(bucket.wait_time(cost), Duration::ZERO);
+        bucket.take(cost);
+        assert_eq!(bucket.wait_time(cost), Duration::from_secs(3));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn zero_refill_turns_it_off() {
+        let mut bucket = TokenBucket::new(RateLimit { refill_per_second: 0.0, ..limit() });
+        for _ in 0..100 {
+            assert_eq!(bucket.wait_time(1.0), Duration::ZERO);
+            bucket.take(1.0);
+        }
+    }
+}
@@ -0,0 +1,62 @@
+// Delays between reconnect attempts: exponential backoff with jitter, so a
+// server restart doesn't get every bot hammering it at the same moment.
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReconnectConfig {
+    pub enabled: bool,
+    pub initial_delay_secs: f64,
+    pub max_delay_secs: f64,
+    // give up after this many failed attempts in a row, 0 keeps trying forever
+    pub max_attempts: u32,
+    // a server that hasn't accepted the connection by then counts as down
+    pub connect_timeout_secs: f64,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        ReconnectConfig { enabled: true, initial_delay_secs: 2.0, max_delay_secs: 300.0, max_attempts: 0, connect_timeout_secs: 30.0 }
+    }
+}
+
+pub struct Backoff {
+    config: ReconnectConfig,
+    attempt: u32,
+}
+
+impl Backoff {
+    pub fn new(config: ReconnectConfig) -> Self {
+        Backoff { config, attempt: 0 }
+    }
+
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    // Delay before the next attempt, or None once max_attempts is used up.
+    pub fn next_delay(&mut self) -> Option<Duration> {
+        if self.config.max_attempts > 0 && self.attempt >= self.config.max_attempts {
+            return None;
+        }
+        let exponent = self.attempt.min(30) as i32;
+        self.attempt += 1;
+        let delay = (self.config.initial_delay_secs * 2f64.powi(exponent)).min(self.config.max_delay_secs);
+        // anywhere between half and the full delay
+        let jittered = rand::thread_rng().gen_range(delay / 2.0..=delay.max(0.001));
+        Some(Duration::from_secs_f64(jittered))
+    }
+
+    // new settings from a reloaded config, attempts so far still count
+    pub fn set_config(&mut self, config: ReconnectConfig) {
+        self.config = config;
+    }
+
+    // call once a connection has been established
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
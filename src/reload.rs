@@ -0,0 +1,202 @@
+// Changing the config of a running client, see IrcClient::reloader.
+//
+// The new config is compared with the old one and applied without dropping
+// the connection where that is possible:
+//   channels     joins the added ones, parts the removed ones; keys,
+//                greetings, auto_rejoin and log take effect right away
+//   nickname     NICK
+//   encoding, keepalive, reconnect   right away
+//   caps, rate_limit                 on the next connection
+// A different server list, network, tls or proxy setting, or different
+// credentials, means logging in again, so the client reconnects instead.
+
+use std::io;
+use tokio::sync::mpsc;
+
+use crate::channels::{self, ChannelConfig};
+use crate::config::BuzzenConfig;
+
+// Handle for handing a running client a new config.
+#[derive(Clone)]
+pub struct Reloader {
+    configs: mpsc::UnboundedSender<BuzzenConfig>,
+}
+
+impl Reloader {
+    pub(crate) fn new() -> (Self, mpsc::UnboundedReceiver<BuzzenConfig>) {
+        let (configs, receiver) = mpsc::unbounded_channel();
+        (Reloader { configs }, receiver)
+    }
+
+    pub fn reload(&self, config: BuzzenConfig) -> io::Result<()> {
+        self.configs
+            .send(config)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Client has stopped"))
+    }
+}
+
+// What differs between two configs.
+#[derive(Debug, Default)]
+pub struct ConfigDiff {
+    pub join: Vec<ChannelConfig>,
+    pub part: Vec<String>,
+    pub nick: Option<String>,
+    // server or credentials changed, only a new login will do
+    pub reconnect: bool,
+    // for the user, e.g. "joined #rust"
+    pub changes: Vec<String>,
+}
+
+impl ConfigDiff {
+    pub fn between(old: &BuzzenConfig, new: &BuzzenConfig) -> Self {
+        let mut diff = ConfigDiff::default();
+
+        let old_channels = old.channel_list();
+        let new_channels = new.channel_list();
+        for channel in &new_channels {
+            match channels::find(&old_channels, &channel.name) {
+                None => {
+                    diff.changes.push(format!("joining {}", channel.name));
+                    diff.join.push(channel.clone());
+                },
+                // the name only differing in case is the same channel
+                Some(before) if *before != ChannelConfig { name: before.name.clone(), ..channel.clone() } => {
+                    diff.changes.push(format!("settings for {}", channel.name))
+                },
+                Some(_) => {},
+            }
+        }
+        for channel in &old_channels {
+            if channels::find(&new_channels, &channel.name).is_none() {
+                diff.changes.push(format!("leaving {}", channel.name));
+                diff.part.push(channel.name.clone());
+            }
+        }
+
+        if !old.nickname.eq_ignore_ascii_case(&new.nickname) {
+            diff.changes.push(format!("nick {} -> {}", old.nickname, new.nickname));
+            diff.nick = Some(new.nickname.clone());
+        }
+
+        let connection = [
+            ("server", old.server_list() != new.server_list()),
+            ("network", old.network != new.network),
+            ("tls", old.tls != new.tls),
+            ("proxy", old.proxy != new.proxy),
+            ("credentials", credentials(old) != credentials(new) || old.sasl != new.sasl),
+        ];
+        for (name, changed) in connection {
+            if changed {
+                diff.changes.push(format!("{} changed, reconnecting", name));
+                diff.reconnect = true;
+            }
+        }
+
+        let settings = [
+            ("encoding", old.encoding != new.encoding),
+            ("keepalive", old.keepalive != new.keepalive),
+            ("reconnect", old.reconnect != new.reconnect),
+            ("caps (next connection)", old.caps != new.caps),
+            ("rate_limit (next connection)", old.rate_limit != new.rate_limit),
+        ];
+        for (name, changed) in settings {
+            if changed {
+                diff.changes.push(name.to_string());
+            }
+        }
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+fn credentials(config: &BuzzenConfig) -> (&str, &str, Option<&str>, Option<&str>) {
+    (&config.email, &config.password, config.password_hash.as_deref(), config.access_token.as_deref())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::{ProxyConfig, ProxyKind};
+    use crate::sasl::{SaslConfig, SaslMechanism};
+    use crate::servers::ServerConfig;
+
+    fn config() -> BuzzenConfig {
+        BuzzenConfig {
+            server: "irc.example.net:6667".to_string(),
+            nickname: "bot".to_string(),
+            password: "hunter2".to_string(),
+            channels: vec![ChannelConfig::new("#a"), ChannelConfig::new("#b")],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn same_config_no_changes() {
+        let diff = ConfigDiff::between(&config(), &config());
+        assert!(diff.is_empty());
+        assert!(!diff.reconnect);
+    }
+
+    #[test]
+    fn channels_are_joined_and_parted() {
+        let new = BuzzenConfig { channels: vec![ChannelConfig::new("#A"), ChannelConfig::new("#c")], ..config() };
+        let diff = ConfigDiff::between(&config(), &new);
+        assert_eq!(diff.join, vec![ChannelConfig::new("#c")]);
+        assert_eq!(diff.part, vec!["#b"]);
+        assert_eq!(diff.changes, vec!["joining #c", "leaving #b"]);
+        assert!(!diff.reconnect);
+
+        // a channel kept with new settings is neither joined nor parted
+        let keyed = ChannelConfig { key: Some("secret".to_string()), ..ChannelConfig::new("#a") };
+        let new = BuzzenConfig { channels: vec![keyed, ChannelConfig::new("#b")], ..config() };
+        let diff = ConfigDiff::between(&config(), &new);
+        assert!(diff.join.is_empty() && diff.part.is_empty());
+        assert_eq!(diff.changes, vec!["settings for #a"]);
+    }
+
+    #[test]
+    fn nick_change_is_a_nick_command() {
+        let diff = ConfigDiff::between(&config(), &BuzzenConfig { nickname: "bot2".to_string(), ..config() });
+        assert_eq!(diff.nick.as_deref(), Some("bot2"));
+        assert!(!diff.reconnect);
+        // only the case changed, the server sees the same nick
+        let diff = ConfigDiff::between(&config(), &BuzzenConfig { nickname: "Bot".to_string(), ..config() });
+        assert_eq!(diff.nick, None);
+    }
+
+    #[test]
+    fn login_changes_reconnect() {
+        let proxy = ProxyConfig { kind: ProxyKind::Socks5, host: "proxy".to_string(), port: 1080, username: None, password: None };
+        let sasl = SaslConfig { mechanism: SaslMechanism::Plain, username: None, password: None };
+        let mut tls = config().tls;
+        tls.enabled = !tls.enabled;
+        let cases = [
+            ("server", BuzzenConfig { server: "other.example.net:6667".to_string(), ..config() }),
+            ("server", BuzzenConfig { servers: vec![ServerConfig::parse("backup.example.net:6667")], ..config() }),
+            ("tls", BuzzenConfig { tls, ..config() }),
+            ("proxy", BuzzenConfig { proxy: Some(proxy), ..config() }),
+            ("credentials", BuzzenConfig { password: "hunter3".to_string(), ..config() }),
+            ("credentials", BuzzenConfig { access_token: Some("token".to_string()), ..config() }),
+            ("credentials", BuzzenConfig { sasl: Some(sasl), ..config() }),
+        ];
+        for (name, new) in cases {
+            let diff = ConfigDiff::between(&config(), &new);
+            assert!(diff.reconnect, "{}", name);
+            assert_eq!(diff.changes, vec![format!("{} changed, reconnecting", name)]);
+        }
+    }
+
+    #[test]
+    fn settings_alone_keep_the_connection() {
+        let mut new = BuzzenConfig { encoding: Some("cp1252".to_string()), caps: vec!["server-time".to_string()], ..config() };
+        new.keepalive.idle_secs += 30.0;
+        new.rate_limit.burst += 1.0;
+        let diff = ConfigDiff::between(&config(), &new);
+        assert!(!diff.reconnect);
+        assert!(diff.join.is_empty() && diff.part.is_empty() && diff.nick.is_none());
+        assert_eq!(diff.changes, vec!["encoding", "keepalive", "caps (next connection)", "rate_limit (next connection)"]);
+    }
+}
@@ -0,0 +1,424 @@
+// SASL authentication over AUTHENTICATE, once the server has ACKed the
+// "sasl" cap (caps.rs holds CAP END back until this is done).
+//
+//   PLAIN          account name and password, base64 encoded
+//   EXTERNAL       the TLS client certificate (tls.client_cert) proves who we are
+//   SCRAM-SHA-256  challenge-response, the password never crosses the wire
+//
+// Payloads are base64 and sent in 400 byte chunks. A last chunk of exactly
+// 400 bytes is followed by "AUTHENTICATE +" so the server knows it was the end.
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hmac::{Hmac, Mac};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+const CHUNK_SIZE: usize = 400;
+// PBKDF2 rounds we are willing to run, servers use 4096 to 10000; a huge
+// count from the server would stall the client for minutes
+const MAX_SCRAM_ITERATIONS: u32 = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum SaslMechanism {
+    #[serde(rename = "PLAIN")]
+    Plain,
+    #[serde(rename = "EXTERNAL")]
+    External,
+    #[serde(rename = "SCRAM-SHA-256")]
+    ScramSha256,
+}
+
+impl SaslMechanism {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SaslMechanism::Plain => "PLAIN",
+            SaslMechanism::External => "EXTERNAL",
+            SaslMechanism::ScramSha256 => "SCRAM-SHA-256",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SaslConfig {
+    pub mechanism: SaslMechanism,
+    // account name, the nickname is used when this is missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    // the password from the config is used when this is missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+}
+
+enum Step {
+    Start,
+    // SCRAM: waiting for server-first, with our client-first-message-bare
+    ScramFirst { client_first_bare: String, nonce: String },
+    // SCRAM: waiting for server-final, with the signature it should carry
+    ScramFinal { server_signature: Vec<u8> },
+    Done,
+    // we sent "AUTHENTICATE *", the server's 906 is the answer to that
+    Aborted,
+}
+
+pub(crate) struct SaslSession {
+    mechanism: SaslMechanism,
+    username: String,
+    password: String,
+    step: Step,
+    // server payload split over several AUTHENTICATE lines
+    incoming: String,
+}
+
+impl SaslSession {
+    pub(crate) fn new(mechanism: SaslMechanism, username: &str, password: &str) -> Self {
+        SaslSession {
+            mechanism,
+            username: username.to_string(),
+            password: password.to_string(),
+            step: Step::Start,
+            incoming: String::new(),
+        }
+    }
+
+    pub(crate) fn start(&self) -> String {
+        format!("AUTHENTICATE {}", self.mechanism.name())
+    }
+
+    // The reply to one AUTHENTICATE line from the server, nothing while a
+    // chunked payload is still incomplete. Err means the exchange should be
+    // aborted with "AUTHENTICATE *".
+    pub(crate) fn handle(&mut self, data: &str) -> Result<Vec<String>, String> {
+        let reply = self.reply(data);
+        if reply.is_err() {
+            self.step = Step::Aborted;
+        }
+        reply
+    }
+
+    // whether handle() gave up on the exchange
+    pub(crate) fn aborted(&self) -> bool {
+        matches!(self.step, Step::Aborted)
+    }
+
+    fn reply(&mut self, data: &str) -> Result<Vec<String>, String> {
+        if data != "+" {
+            self.incoming.push_str(data);
+            if data.len() == CHUNK_SIZE {
+                return Ok(Vec::new());
+            }
+        }
+        let payload = std::mem::take(&mut self.incoming);
+        let challenge = STANDARD.decode(payload).map_err(|_| "Server sent invalid base64".to_string())?;
+        let response = self.respond(&challenge)?;
+        Ok(chunk(&response))
+    }
+
+    fn respond(&mut self, challenge: &[u8]) -> Result<Vec<u8>, String> {
+        match (self.mechanism, &self.step) {
+            (SaslMechanism::Plain, Step::Start) => {
+                self.step = Step::Done;
+                Ok(format!("\0{}\0{}", self.username, self.password).into_bytes())
+            },
+            (SaslMechanism::External, Step::Start) => {
+                self.step = Step::Done;
+                Ok(Vec::new())
+            },
+            (SaslMechanism::ScramSha256, Step::Start) => {
+                let nonce: String = rand::thread_rng().sample_iter(&Alphanumeric).take(24).map(char::from).collect();
+                let client_first_bare = format!("n={},r={}", scram_escape(&self.username), nonce);
+                let message = format!("n,,{}", client_first_bare);
+                self.step = Step::ScramFirst { client_first_bare, nonce };
+                Ok(message.into_bytes())
+            },
+            (SaslMechanism::ScramSha256, Step::ScramFirst { client_first_bare, nonce }) => {
+                let server_first = String::from_utf8(challenge.to_vec()).map_err(|_| "Invalid SCRAM challenge".to_string())?;
+                let (client_final, server_signature) = scram_final(&self.password, client_first_bare, nonce, &server_first)?;
+                self.step = Step::ScramFinal { server_signature };
+                Ok(client_final.into_bytes())
+            },
+            (SaslMechanism::ScramSha256, Step::ScramFinal { server_signature }) => {
+                // check the server knew the password too before we trust it
+                let server_final = String::from_utf8_lossy(challenge).to_string();
+                let verifier = scram_attribute(&server_final, 'v').ok_or_else(|| format!("SCRAM failed: {}", server_final))?;
+                if STANDARD.decode(verifier).ok().as_ref() != Some(server_signature) {
+                    return Err("SCRAM server signature does not match".to_string());
+                }
+                self.step = Step::Done;
+                Ok(Vec::new())
+            },
+            _ => Err("Unexpected AUTHENTICATE from server".to_string()),
+        }
+    }
+}
+
+// client-final-message and the server signature to expect back
+fn scram_final(password: &str, client_first_bare: &str, nonce: &str, server_first: &str) -> Result<(String, Vec<u8>), String> {
+    let invalid = || format!("Invalid SCRAM challenge: {}", server_first);
+    let server_nonce = scram_attribute(server_first, 'r').ok_or_else(invalid)?;
+    let salt = scram_attribute(server_first, 's').and_then(|salt| STANDARD.decode(salt).ok()).ok_or_else(invalid)?;
+    let iterations: u32 = scram_attribute(server_first, 'i').and_then(|i| i.parse().ok()).ok_or_else(invalid)?;
+    if !server_nonce.starts_with(nonce) {
+        return Err("SCRAM server nonce does not extend ours".to_string());
+    }
+    if iterations == 0 || iterations > MAX_SCRAM_ITERATIONS {
+        return Err(format!("SCRAM iteration count {} is out of range", iterations));
+    }
+
+    let mut salted_password = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), &salt, iterations, &mut salted_password);
+    let client_key = hmac(&salted_password, b"Client Key");
+    let stored_key = Sha256::digest(&client_key);
+    let server_key = hmac(&salted_password, b"Server Key");
+
+    // "biws" is base64 of "n,,", the GS2 header without channel binding
+    let without_proof = format!("c=biws,r={}", server_nonce);
+    let auth_message = format!("{},{},{}", client_first_bare, server_first, without_proof);
+    let client_signature = hmac(&stored_key, auth_message.as_bytes());
+    let proof: Vec<u8> = client_key.iter().zip(&client_signature).map(|(key, signature)| key ^ signature).collect();
+
+    let client_final = format!("{},p={}", without_proof, STANDARD.encode(proof));
+    Ok((client_final, hmac(&server_key, auth_message.as_bytes())))
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+// value of "x=..." in a comma separated SCRAM message
+fn scram_attribute(message: &str, name: char) -> Option<&str> {
+    message.split(',').find_map(|part| part.strip_prefix(name)?.strip_prefix('='))
+}
+
+fn scram_escape(name: &str) -> String {
+    name.replace('=', "=3D").replace(',', "=2C")
+}
+
+// AUTHENTICATE lines for a response payload
+fn chunk(response: &[u8]) -> Vec<String> {
+    let encoded = STANDARD.encode(response);
+    let mut lines: Vec<String> = encoded
+        .as_bytes()
+        .chunks(CHUNK_SIZE)
+        .map(|chunk| format!("AUTHENTICATE {}", String::from_utf8_lossy(chunk)))
+        .collect();
+    if encoded.len().is_multiple_of(CHUNK_SIZE) {
+        lines.push("AUTHENTICATE +".to_string());
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::Event;
+    use crate::mock::{self, MockServer};
+    use crate::IrcClient;
+
+    fn encoded(payload: &str) -> String {
+        STANDARD.encode(payload)
+    }
+
+    #[test]
+    fn plain_payload() {
+        let mut session = SaslSession::new(SaslMechanism::Plain, "user", "hunter2");
+        assert_eq!(session.start(), "AUTHENTICATE PLAIN");
+        assert_eq!(session.handle("+").unwrap(), vec![format!("AUTHENTICATE {}", encoded("\0user\0hunter2"))]);
+    }
+
+    #[test]
+    fn external_payload_is_empty() {
+        let mut session = SaslSession::new(SaslMechanism::External, "user", "");
+        assert_eq!(session.start(), "AUTHENTICATE EXTERNAL");
+        assert_eq!(session.handle("+").unwrap(), vec!["AUTHENTICATE +"]);
+    }
+
+    #[test]
+    fn outgoing_payloads_are_chunked() {
+        // 300 bytes of payload are exactly 400 bytes of base64, so an empty chunk follows
+        let mut session = SaslSession::new(SaslMechanism::Plain, "u", &"p".repeat(297));
+        let lines = session.handle("+").unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].len(), "AUTHENTICATE ".len() + CHUNK_SIZE);
+        assert_eq!(lines[1], "AUTHENTICATE +");
+
+        let mut session = SaslSession::new(SaslMechanism::Plain, "u", &"p".repeat(447));
+        let lines = session.handle("+").unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].len(), "AUTHENTICATE ".len() + CHUNK_SIZE);
+        assert_eq!(lines[1].len(), "AUTHENTICATE ".len() + 200);
+        let payload: String = lines.iter().map(|line| &line["AUTHENTICATE ".len()..]).collect();
+        assert_eq!(STANDARD.decode(payload).unwrap(), format!("\0u\0{}", "p".repeat(447)).into_bytes());
+    }
+
+    #[test]
+    fn incoming_payloads_are_reassembled() {
+        let mut session = SaslSession::new(SaslMechanism::Plain, "user", "pass");
+        assert_eq!(session.handle(&"A".repeat(CHUNK_SIZE)).unwrap(), Vec::<String>::new());
+        assert_eq!(session.handle("QUFB").unwrap().len(), 1);
+
+        // a payload of exactly 400 bytes ends with "+"
+        let mut session = SaslSession::new(SaslMechanism::Plain, "user", "pass");
+        assert_eq!(session.handle(&"A".repeat(CHUNK_SIZE)).unwrap(), Vec::<String>::new());
+        assert_eq!(session.handle("+").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn invalid_base64_aborts() {
+        let mut session = SaslSession::new(SaslMechanism::Plain, "user", "pass");
+        assert!(session.handle("not base64!").is_err());
+        assert!(session.aborted());
+    }
+
+    // RFC 7677 section 3
+    const CLIENT_NONCE: &str = "rOprNGfwEbeRWgbNEkqO";
+    const SERVER_FIRST: &str = "r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096";
+    const CLIENT_FINAL: &str = "c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ=";
+    const SERVER_FINAL: &str = "v=6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4=";
+
+    // a SCRAM session past client-first, with the RFC's nonce instead of a random one
+    fn scram_session() -> SaslSession {
+        let mut session = SaslSession::new(SaslMechanism::ScramSha256, "user", "pencil");
+        let client_first = session.handle("+").unwrap();
+        let client_first = String::from_utf8(STANDARD.decode(&client_first[0]["AUTHENTICATE ".len()..]).unwrap()).unwrap();
+        assert!(client_first.starts_with("n,,n=user,r="), "{}", client_first);
+        session.step = Step::ScramFirst { client_first_bare: format!("n=user,r={}", CLIENT_NONCE), nonce: CLIENT_NONCE.to_string() };
+        session
+    }
+
+    #[test]
+    fn scram_sha256_rfc7677_exchange() {
+        let mut session = scram_session();
+        let client_final = session.handle(&encoded(SERVER_FIRST)).unwrap();
+        assert_eq!(client_final, vec![format!("AUTHENTICATE {}", encoded(CLIENT_FINAL))]);
+        assert_eq!(session.handle(&encoded(SERVER_FINAL)).unwrap(), vec!["AUTHENTICATE +"]);
+        assert!(matches!(session.step, Step::Done));
+    }
+
+    #[test]
+    fn scram_wrong_server_signature() {
+        let mut session = scram_session();
+        session.handle(&encoded(SERVER_FIRST)).unwrap();
+        let forged = format!("v={}", STANDARD.encode([0u8; 32]));
+        assert_eq!(session.handle(&encoded(&forged)).unwrap_err(), "SCRAM server signature does not match");
+        assert!(session.aborted());
+    }
+
+    #[test]
+    fn scram_nonce_must_extend_ours() {
+        let mut session = scram_session();
+        let server_first = "r=someoneelse,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096";
+        assert_eq!(session.handle(&encoded(server_first)).unwrap_err(), "SCRAM server nonce does not extend ours");
+    }
+
+    #[test]
+    fn scram_escapes_names() {
+        assert_eq!(scram_escape("a=b,c"), "a=3Db=2Cc");
+    }
+
+    // A client logging in with SASL PLAIN, up to the point where it has sent
+    // AUTHENTICATE with its credentials.
+    async fn start_plain() -> (MockServer, tokio::task::JoinHandle<Vec<Event>>) {
+        let mut config = mock::config();
+        config.sasl = Some(SaslConfig { mechanism: SaslMechanism::Plain, username: None, password: Some("pass".to_string()) });
+        let (mut server, client) = mock::connect(IrcClient::new(&config).unwrap()).await;
+        server.expect("CAP LS 302").await;
+        server.expect("NICK bot").await;
+        server.expect("USER bot 0 * :RustBot").await;
+        server.send(":srv CAP * LS :sasl").await;
+        server.expect("CAP REQ :sasl").await;
+        server.send(":srv CAP * ACK :sasl").await;
+        server.expect("AUTHENTICATE PLAIN").await;
+        server.send("AUTHENTICATE +").await;
+        server.expect(&format!("AUTHENTICATE {}", encoded("\0bot\0pass"))).await;
+        (server, client)
+    }
+
+    fn sasl_failures(events: &[Event]) -> Vec<&str> {
+        events
+            .iter()
+            .filter_map(|event| match event {
+                Event::SaslFailed { reason } => Some(reason.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn client_sasl_success() {
+        let (mut server, client) = start_plain().await;
+        server.send(":srv 900 bot bot!u@h bot :You are now logged in as bot").await;
+        server.send(":srv 903 bot :SASL authentication successful").await;
+        server.expect("CAP END").await;
+        drop(server);
+        assert!(sasl_failures(&client.await.unwrap()).is_empty());
+    }
+
+    #[tokio::test]
+    async fn client_sasl_failure() {
+        let (mut server, client) = start_plain().await;
+        server.send(":srv 904 bot :SASL authentication failed").await;
+        server.expect("CAP END").await;
+        drop(server);
+        assert_eq!(sasl_failures(&client.await.unwrap()), vec!["SASL authentication failed"]);
+    }
+
+    #[tokio::test]
+    async fn client_sasl_abort_is_reported_once() {
+        let (mut server, client) = start_plain().await;
+        // PLAIN is done after one reply, another challenge makes the client give up
+        server.send("AUTHENTICATE +").await;
+        server.expect("AUTHENTICATE *").await;
+        server.send(":srv 906 bot :SASL authentication aborted").await;
+        server.expect("CAP END").await;
+        drop(server);
+        assert_eq!(sasl_failures(&client.await.unwrap()), vec!["Unexpected AUTHENTICATE from server"]);
+    }
+
+    async fn start_without_sasl_cap(reply: &str) -> Vec<Event> {
+        let mut config = mock::config();
+        config.sasl = Some(SaslConfig { mechanism: SaslMechanism::Plain, username: None, password: Some("pass".to_string()) });
+        let (mut server, client) = mock::connect(IrcClient::new(&config).unwrap()).await;
+        server.expect("CAP LS 302").await;
+        server.expect("NICK bot").await;
+        server.expect("USER bot 0 * :RustBot").await;
+        server.send(reply).await;
+        if reply.contains("CAP * LS :") && reply.contains("sasl") {
+            server.expect("CAP REQ :sasl").await;
+            server.send(":srv CAP * NAK :sasl").await;
+        }
+        if reply.contains("CAP") {
+            server.expect("CAP END").await;
+        }
+        drop(server);
+        client.await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn client_sasl_cap_not_offered() {
+        let events = start_without_sasl_cap(":srv CAP * LS :multi-prefix").await;
+        assert_eq!(sasl_failures(&events), vec!["Server did not enable the sasl cap, logging in without SASL"]);
+    }
+
+    #[tokio::test]
+    async fn client_sasl_cap_refused() {
+        let events = start_without_sasl_cap(":srv CAP * LS :sasl").await;
+        assert_eq!(sasl_failures(&events), vec!["Server did not enable the sasl cap, logging in without SASL"]);
+    }
+
+    #[tokio::test]
+    async fn client_sasl_without_cap_support() {
+        let events = start_without_sasl_cap(":srv 001 bot :Welcome bot!u@h").await;
+        assert_eq!(sasl_failures(&events), vec!["Server does not support CAP, logged in without SASL"]);
+    }
+
+    #[test]
+    fn scram_iteration_count_is_bounded() {
+        let mut session = scram_session();
+        let server_first = format!("r={}xyz,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4000000000", CLIENT_NONCE);
+        assert_eq!(session.handle(&encoded(&server_first)).unwrap_err(), "SCRAM iteration count 4000000000 is out of range");
+        assert!(session.aborted());
+    }
+}
@@ -0,0 +1,137 @@
+// Credentials that don't have to sit in config.json. Looked up in order, the
+// first one found wins:
+//
+//   RUSTYIRC_<NETWORK>_PASSWORD & co., with the network's name in capitals
+//   RUSTYIRC_EMAIL, RUSTYIRC_PASSWORD, RUSTYIRC_PASSWORD_HASH, RUSTYIRC_ACCESS_TOKEN
+//       (only when config.json has a single network)
+//   "secrets_file" in config.json, JSON with the same keys in lowercase:
+//       {"password": "hunter2"} or {"password_hash": "<md5 hex>", "email": "..."}
+//   config.json itself
+//
+// The secrets file has to be readable by its owner only (chmod 600).
+// password_hash is the md5 hex digest Buzzen's LOGINH wants, for keeping the
+// password itself out of every file.
+
+use serde::Deserialize;
+use std::{env, fs, io::{self, Write}};
+
+use crate::config::{BuzzenConfig, Config};
+
+#[derive(Debug, Default, Deserialize)]
+struct Secrets {
+    email: Option<String>,
+    password: Option<String>,
+    password_hash: Option<String>,
+    access_token: Option<String>,
+}
+
+// Fill in config from the secrets file and the environment. Err says what is
+// wrong with the secrets file.
+pub(crate) fn apply(config: &mut BuzzenConfig, shared_env: bool) -> Result<(), String> {
+    if let Some(path) = config.secrets_file.clone() {
+        check_private(&path)?;
+        let contents = fs::read_to_string(&path).map_err(|err| format!("Could not read {}: {}", path, err))?;
+        let secrets: Secrets = serde_json::from_str(&contents).map_err(|err| format!("Could not read {}: {}", path, err))?;
+        overlay(config, secrets);
+    }
+    let prefix: String = config.network_name().chars().map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' }).collect();
+    let var = |key: &str| {
+        let own = env::var(format!("RUSTYIRC_{}_{}", prefix, key)).ok();
+        let shared = if shared_env { env::var(format!("RUSTYIRC_{}", key)).ok() } else { None };
+        own.or(shared).filter(|value| !value.is_empty())
+    };
+    overlay(config, Secrets {
+        email: var("EMAIL"),
+        password: var("PASSWORD"),
+        password_hash: var("PASSWORD_HASH"),
+        access_token: var("ACCESS_TOKEN"),
+    });
+    Ok(())
+}
+
+fn overlay(config: &mut BuzzenConfig, secrets: Secrets) {
+    if let Some(email) = secrets.email {
+        config.email = email;
+    }
+    if let Some(password) = secrets.password {
+        config.password = password;
+        // a password given here beats a hash from further down
+        config.password_hash = None;
+    }
+    if let Some(hash) = secrets.password_hash {
+        config.password_hash = Some(hash);
+    }
+    if let Some(token) = secrets.access_token {
+        config.access_token = Some(token);
+    }
+}
+
+// A warning when anyone on the machine can read the config file and it holds
+// a password or token.
+pub fn permission_warning(filename: &str, config: &Config) -> Option<String> {
+    if config.networks.iter().any(has_credentials) && world_readable(filename) {
+        return Some(format!(
+            "{} is readable by every user and has credentials in it, run: chmod 600 {} (or move them to a secrets file)",
+            filename, filename
+        ));
+    }
+    None
+}
+
+// whether writing config to a file puts a password or token in it
+pub(crate) fn has_credentials(config: &BuzzenConfig) -> bool {
+    !config.password.is_empty()
+        || config.password_hash.is_some()
+        || config.access_token.is_some()
+        || config.sasl.as_ref().is_some_and(|sasl| sasl.password.is_some())
+        || config.proxy.as_ref().is_some_and(|proxy| proxy.password.is_some())
+}
+
+// Write a config file, readable by its owner only when it holds credentials,
+// so saving one never leaves it for permission_warning to complain about.
+pub(crate) fn write_config(filename: &str, json: &str, private: bool) -> io::Result<()> {
+    if !private {
+        return fs::write(filename, json);
+    }
+    open_private(filename)?.write_all(json.as_bytes())
+}
+
+// chmod'ed before anything is written, an existing file keeps its mode otherwise
+#[cfg(unix)]
+fn open_private(path: &str) -> io::Result<fs::File> {
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+    let file = fs::OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(path)?;
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    Ok(file)
+}
+
+#[cfg(not(unix))]
+fn open_private(path: &str) -> io::Result<fs::File> {
+    fs::File::create(path)
+}
+
+#[cfg(unix)]
+fn world_readable(path: &str) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path).is_ok_and(|metadata| metadata.permissions().mode() & 0o004 != 0)
+}
+
+#[cfg(not(unix))]
+fn world_readable(_path: &str) -> bool {
+    false
+}
+
+#[cfg(unix)]
+fn check_private(path: &str) -> Result<(), String> {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = fs::metadata(path).map_err(|err| format!("Could not read {}: {}", path, err))?.permissions().mode() & 0o777;
+    if mode & 0o077 != 0 {
+        return Err(format!("{} is mode {:o}, it should only be readable by you: chmod 600 {}", path, mode, path));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_private(_path: &str) -> Result<(), String> {
+    Ok(())
+}
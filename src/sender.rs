@@ -0,0 +1,198 @@
+// Cloneable handle for sending lines to the server from anywhere: handlers,
+// the terminal, timers. Lines are queued and written by the writer task
+// (writer.rs) in the order they were sent. Until the server has welcomed
+// us (001) they stay queued, only the client's own login lines go out.
+
+use std::io;
+use std::sync::{atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering}, Arc, RwLock};
+use std::time::Duration;
+use tokio::sync::{mpsc, watch, Notify};
+
+use crate::tags::Tags;
+
+#[derive(Clone)]
+pub struct Sender {
+    queue: mpsc::UnboundedSender<String>,
+    // PING/PONG skip the rate limiter and everything queued before them,
+    // so keepalives and lag measurements aren't stuck behind a flood
+    priority: mpsc::UnboundedSender<String>,
+    // the client's own lines (CAP, SASL, rejoins), never held back
+    own: mpsc::UnboundedSender<String>,
+    depth: Arc<AtomicUsize>,
+    // poked when a QUIT is queued, so a client waiting to reconnect stops
+    quitting: Arc<Notify>,
+    // keepalive round trip in microseconds, NO_LAG until the first PONG
+    lag: Arc<AtomicU64>,
+    // "host:port" of the server the client is on, or was last
+    server: Arc<RwLock<String>>,
+}
+
+const NO_LAG: u64 = u64::MAX;
+
+// The receiving end, owned by the writer task.
+pub(crate) struct Outbound {
+    pub(crate) queue: mpsc::UnboundedReceiver<String>,
+    pub(crate) priority: mpsc::UnboundedReceiver<String>,
+    pub(crate) own: mpsc::UnboundedReceiver<String>,
+    pub(crate) depth: Arc<AtomicUsize>,
+    // true from 001 until the connection drops; the queue waits for it
+    pub(crate) registered: Arc<watch::Sender<bool>>,
+    // taken off the queue but not written yet, so a writer aborted by a
+    // disconnect doesn't lose it
+    pub(crate) in_flight: Option<String>,
+    // set once a QUIT has been written, so the client doesn't reconnect
+    pub(crate) quit: Arc<AtomicBool>,
+    pub(crate) quitting: Arc<Notify>,
+}
+
+impl Sender {
+    pub(crate) fn new() -> (Self, Outbound) {
+        let (queue, queue_rx) = mpsc::unbounded_channel();
+        let (priority, priority_rx) = mpsc::unbounded_channel();
+        let (own, own_rx) = mpsc::unbounded_channel();
+        let depth = Arc::new(AtomicUsize::new(0));
+        let quit = Arc::new(AtomicBool::new(false));
+        let quitting = Arc::new(Notify::new());
+        let outbound = Outbound {
+            queue: queue_rx,
+            priority: priority_rx,
+            own: own_rx,
+            depth: depth.clone(),
+            registered: Arc::new(watch::Sender::new(false)),
+            in_flight: None,
+            quit,
+            quitting: quitting.clone(),
+        };
+        let lag = Arc::new(AtomicU64::new(NO_LAG));
+        let server = Arc::new(RwLock::new(String::new()));
+        (Sender { queue, priority, own, depth, quitting, lag, server }, outbound)
+    }
+
+    // Queue a raw line, without the line ending. A CR or LF inside would
+    // let text smuggle in a second command, so that is an error.
+    pub fn send(&self, line: &str) -> io::Result<()> {
+        check_line(line)?;
+        let closed = |_| io::Error::new(io::ErrorKind::BrokenPipe, "Connection is closed");
+        if line.starts_with("PONG") || line.starts_with("PING") {
+            return self.priority.send(line.to_string()).map_err(closed);
+        }
+        self.depth.fetch_add(1, Ordering::Relaxed);
+        self.queue.send(line.to_string()).map_err(|err| {
+            self.depth.fetch_sub(1, Ordering::Relaxed);
+            closed(err)
+        })?;
+        if line.get(..4).is_some_and(|command| command.eq_ignore_ascii_case("QUIT")) {
+            self.quitting.notify_one();
+        }
+        Ok(())
+    }
+
+    // For the client itself: written even before 001, but dropped if the
+    // connection goes before they are.
+    pub(crate) fn send_own(&self, line: &str) -> io::Result<()> {
+        check_line(line)?;
+        let closed = |_| io::Error::new(io::ErrorKind::BrokenPipe, "Connection is closed");
+        if line.starts_with("PONG") || line.starts_with("PING") {
+            return self.priority.send(line.to_string()).map_err(closed);
+        }
+        self.own.send(line.to_string()).map_err(closed)
+    }
+
+    // Queue a line with message tags in front, e.g. client-only tags:
+    //   sender.send_tagged(&Tags::new().with("+draft/react", "👍"), "TAGMSG #channel")
+    pub fn send_tagged(&self, tags: &Tags, line: &str) -> io::Result<()> {
+        if tags.is_empty() {
+            return self.send(line);
+        }
+        self.send(&format!("@{} {}", tags, line))
+    }
+
+    // Round trip of the last keepalive PING on this connection, None until
+    // the first PONG.
+    pub fn lag(&self) -> Option<Duration> {
+        match self.lag.load(Ordering::Relaxed) {
+            NO_LAG => None,
+            micros => Some(Duration::from_micros(micros)),
+        }
+    }
+
+    pub(crate) fn set_lag(&self, lag: Option<Duration>) {
+        let micros = lag.map_or(NO_LAG, |lag| (lag.as_micros() as u64).min(NO_LAG - 1));
+        self.lag.store(micros, Ordering::Relaxed);
+    }
+
+    // The server the client is connected to, or was last, as "host:port".
+    pub fn server(&self) -> String {
+        self.server.read().unwrap_or_else(|err| err.into_inner()).clone()
+    }
+
+    pub(crate) fn set_server(&self, server: &str) {
+        *self.server.write().unwrap_or_else(|err| err.into_inner()) = server.to_string();
+    }
+
+    // Lines waiting for the rate limiter.
+    pub fn queue_depth(&self) -> usize {
+        self.depth.load(Ordering::Relaxed)
+    }
+
+    pub fn privmsg(&self, target: &str, text: &str) -> io::Result<()> {
+        self.send(&format!("PRIVMSG {} :{}", target, text))
+    }
+
+    // a PRIVMSG threaded under another message, msgid from its ctx.tags
+    pub fn reply(&self, target: &str, msgid: &str, text: &str) -> io::Result<()> {
+        self.send_tagged(&Tags::new().with("+draft/reply", msgid), &format!("PRIVMSG {} :{}", target, text))
+    }
+
+    // typing notification: state is "active", "paused" or "done"
+    pub fn typing(&self, target: &str, state: &str) -> io::Result<()> {
+        self.send_tagged(&Tags::new().with("+typing", state), &format!("TAGMSG {}", target))
+    }
+
+    pub fn notice(&self, target: &str, text: &str) -> io::Result<()> {
+        self.send(&format!("NOTICE {} :{}", target, text))
+    }
+
+    pub fn action(&self, target: &str, text: &str) -> io::Result<()> {
+        self.privmsg(target, &format!("\u{0001}ACTION {}\u{0001}", text))
+    }
+
+    // Buzzen private message inside a channel
+    pub fn whisper(&self, channel: &str, nick: &str, text: &str) -> io::Result<()> {
+        self.send(&format!("WHISPER {} {} :{}", channel, nick, text))
+    }
+
+    pub fn ctcp_reply(&self, target: &str, ctcp_type: &str, text: &str) -> io::Result<()> {
+        self.notice(target, &format!("\u{0001}{} {}\u{0001}", ctcp_type, text))
+    }
+
+    pub fn join(&self, channel: &str) -> io::Result<()> {
+        self.send(&format!("JOIN {}", channel))
+    }
+
+    pub fn part(&self, channel: &str) -> io::Result<()> {
+        self.send(&format!("PART {}", channel))
+    }
+}
+
+fn check_line(line: &str) -> io::Result<()> {
+    if line.contains(['\r', '\n']) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Line break in outgoing line: {:?}", line)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_breaks_are_refused() {
+        let (sender, mut outbound) = Sender::new();
+        let err = sender.privmsg("#a", "hi\r\nQUIT :bye").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(sender.send("JOIN #b\n").is_err());
+        assert_eq!(sender.queue_depth(), 0);
+        assert!(outbound.queue.try_recv().is_err());
+    }
+}
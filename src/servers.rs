@@ -0,0 +1,76 @@
+// The "servers" list in config.json, tried in order. When a server refuses
+// the connection, times out or drops us, the next reconnect goes to the
+// next one, and back to the top after the last. Each entry is "host:port"
+// or an object:
+//
+//   "servers": ["irc.example.net:6697", {"host": "backup.example.net", "port": 6667, "tls": false}]
+//
+//   tls  use TLS for this server, "tls"."enabled" decides when it is left out
+//
+// The older single "server" setting still works and is tried first.
+
+use serde::{Deserialize, Serialize};
+
+use crate::transport::{self, TlsConfig};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "ServerEntry")]
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls: Option<bool>,
+}
+
+impl ServerConfig {
+    // "host:port"; anything else keeps the text as host with port 0, for
+    // validate() to complain about
+    pub fn parse(address: &str) -> Self {
+        match transport::split_server(address) {
+            Ok((host, port)) => ServerConfig { host: host.to_string(), port, tls: None },
+            Err(_) => ServerConfig { host: address.to_string(), port: 0, tls: None },
+        }
+    }
+
+    pub fn address(&self) -> String {
+        if self.host.contains(':') {
+            format!("[{}]:{}", self.host, self.port)
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+
+    // the TLS settings for this server, tls overriding "enabled"
+    pub fn tls_config(&self, tls: &TlsConfig) -> TlsConfig {
+        TlsConfig { enabled: self.tls.unwrap_or(tls.enabled), ..tls.clone() }
+    }
+
+    // what is wrong with the entry, for BuzzenConfig::validate
+    pub fn check(&self) -> Result<(), String> {
+        if self.host.is_empty() || self.port == 0 {
+            return Err(format!("Server should be host:port, got {:?}", self.host));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ServerEntry {
+    Address(String),
+    Full {
+        host: String,
+        port: u16,
+        #[serde(default)]
+        tls: Option<bool>,
+    },
+}
+
+impl From<ServerEntry> for ServerConfig {
+    fn from(entry: ServerEntry) -> Self {
+        match entry {
+            ServerEntry::Address(address) => ServerConfig::parse(&address),
+            ServerEntry::Full { host, port, tls } => ServerConfig { host, port, tls },
+        }
+    }
+}
@@ -0,0 +1,118 @@
+// First-run wizard: asks for the few things a config can't do without and
+// builds a BuzzenConfig from the answers. Runs when there is no config.json
+// and someone is at the terminal, or with --setup to edit an existing one.
+// Enter keeps the value shown in [brackets].
+
+use std::io::{self, BufRead, IsTerminal, Write};
+
+use crate::channels::{self, ChannelConfig};
+use crate::config::BuzzenConfig;
+use crate::login::Network;
+use crate::secrets;
+
+// whether there is someone to ask
+pub fn is_interactive() -> bool {
+    io::stdin().is_terminal()
+}
+
+// Ask until the answers make a valid config. Settings the wizard doesn't ask
+// about (tls, proxy, ...) are kept from existing. Credentials already in the
+// environment or a secrets_file count as answered but aren't written into
+// the config, shared_env as for secrets::apply.
+pub fn run(existing: Option<&BuzzenConfig>, shared_env: bool) -> io::Result<BuzzenConfig> {
+    let mut config = existing.cloned().unwrap_or_default();
+    println!("Setting up config.json, press enter to keep the value in [brackets].");
+    loop {
+        config.network = ask_network(config.network)?;
+        let default_server = match (config.network, config.server.is_empty()) {
+            (Network::Twitch, true) => "irc.chat.twitch.tv:6667".to_string(),
+            _ => config.server.clone(),
+        };
+        config.server = ask("Server (host:port)", &default_server)?;
+        config.nickname = ask("Nickname", &config.nickname)?;
+
+        let has_password = !with_secrets(&config, shared_env).password.is_empty() || config.password_hash.is_some();
+        match config.network {
+            Network::Buzzen => {
+                config.email = ask("Account email", &config.email)?;
+                config.password = ask_password("Password", has_password)?.unwrap_or(config.password);
+            },
+            Network::Irc => {
+                config.password = ask_password("Server password (optional)", has_password)?.unwrap_or(config.password);
+            },
+            Network::Twitch => {
+                if let Some(token) = ask_password("OAuth token", with_secrets(&config, shared_env).access_token.is_some())? {
+                    config.access_token = Some(token);
+                }
+            },
+        }
+
+        let names: Vec<String> = config.channel_list().into_iter().map(|channel| channel.name).collect();
+        let answer = ask("Channels (comma separated)", &names.join(","))?;
+        config.channels = answer
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            // channels that were there before keep their settings
+            .map(|name| channels::find(&config.channel_list(), name).cloned().unwrap_or_else(|| ChannelConfig::new(name)))
+            .collect();
+        config.channel.clear();
+
+        let mut problems = Vec::new();
+        let mut checked = config.clone();
+        if let Err(reason) = secrets::apply(&mut checked, shared_env) {
+            problems.push(reason);
+        }
+        problems.extend(checked.validate());
+        if problems.is_empty() {
+            return Ok(config);
+        }
+        println!("That config has problems:");
+        for problem in problems {
+            println!("  - {}", problem);
+        }
+        println!("Let's go through it again.");
+    }
+}
+
+// config as the client will see it, for deciding what still needs asking
+fn with_secrets(config: &BuzzenConfig, shared_env: bool) -> BuzzenConfig {
+    let mut config = config.clone();
+    // a broken secrets_file is reported once the answers are checked
+    let _ = secrets::apply(&mut config, shared_env);
+    config
+}
+
+fn ask(question: &str, default: &str) -> io::Result<String> {
+    if default.is_empty() {
+        print!("{}: ", question);
+    } else {
+        print!("{} [{}]: ", question, default);
+    }
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    if io::stdin().lock().read_line(&mut answer)? == 0 {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "setup cancelled"));
+    }
+    let answer = answer.trim();
+    Ok(if answer.is_empty() { default.to_string() } else { answer.to_string() })
+}
+
+// typed without echo; None keeps the one there is
+fn ask_password(question: &str, has_one: bool) -> io::Result<Option<String>> {
+    let prompt = if has_one { format!("{} [unchanged]: ", question) } else { format!("{}: ", question) };
+    let answer = rpassword::prompt_password(prompt)?;
+    Ok(if answer.is_empty() && has_one { None } else { Some(answer) })
+}
+
+fn ask_network(current: Network) -> io::Result<Network> {
+    loop {
+        match ask("Network (buzzen, irc or twitch)", current.as_str())?.to_lowercase().as_str() {
+            "buzzen" => return Ok(Network::Buzzen),
+            "irc" => return Ok(Network::Irc),
+            "twitch" => return Ok(Network::Twitch),
+            other => println!("{:?} is not one of buzzen, irc or twitch", other),
+        }
+    }
+}
@@ -0,0 +1,169 @@
+// IRCv3 message tags: the "@key=value;other" part in front of a line.
+//
+// Incoming tags arrive with every event (ctx.tags for handlers, Envelope::tags
+// for subscribers). Outgoing lines can carry client-only tags ("+" keys) such
+// as +draft/reply or +typing, see Sender::send_tagged. The server has to have
+// the message-tags cap enabled to pass those on.
+
+use serde::ser::{Serialize, SerializeMap, Serializer};
+use std::fmt;
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Tags(Vec<(String, String)>);
+
+impl Tags {
+    pub fn new() -> Self {
+        Tags(Vec::new())
+    }
+
+    // "key=value;key2" without the leading @, values unescaped
+    pub fn parse(raw: &str) -> Self {
+        let tags = raw
+            .split(';')
+            .filter(|tag| !tag.is_empty())
+            .map(|tag| {
+                let (key, value) = tag.split_once('=').unwrap_or((tag, ""));
+                (key.to_string(), unescape(value))
+            })
+            .collect();
+        Tags(tags)
+    }
+
+    // add a tag, builder style: Tags::new().with("+draft/reply", msgid)
+    pub fn with(mut self, key: &str, value: &str) -> Self {
+        self.insert(key, value);
+        self
+    }
+
+    // set a tag, replacing an earlier value for the same key
+    pub fn insert(&mut self, key: &str, value: &str) {
+        match self.0.iter_mut().find(|(existing, _)| existing == key) {
+            Some((_, existing)) => *existing = value.to_string(),
+            None => self.0.push((key.to_string(), value.to_string())),
+        }
+    }
+
+    // Some("") for tags sent without a value
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.iter().find(|(existing, _)| existing == key).map(|(_, value)| value.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    // id of the message, to reply to it with +draft/reply
+    pub fn msgid(&self) -> Option<&str> {
+        self.get("msgid")
+    }
+}
+
+// "key=value;key2", values escaped, ready to go after the @
+impl fmt::Display for Tags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (key, value)) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(";")?;
+            }
+            f.write_str(key)?;
+            if !value.is_empty() {
+                write!(f, "={}", escape(value))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// {"key": "value"}, e.g. for --json output
+impl Serialize for Tags {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (key, value) in &self.0 {
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
+    }
+}
+
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some(':') => unescaped.push(';'),
+            Some('s') => unescaped.push(' '),
+            Some('r') => unescaped.push('\r'),
+            Some('n') => unescaped.push('\n'),
+            // "\\" is a backslash, any other escaped character stands for itself
+            Some(other) => unescaped.push(other),
+            // a lone backslash at the end is dropped
+            None => {},
+        }
+    }
+    unescaped
+}
+
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            ';' => escaped.push_str("\\:"),
+            ' ' => escaped.push_str("\\s"),
+            '\\' => escaped.push_str("\\\\"),
+            '\r' => escaped.push_str("\\r"),
+            '\n' => escaped.push_str("\\n"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unescapes_values() {
+        let tags = Tags::parse("a=one\\:two;b=x\\sy;c=back\\\\slash;d=cr\\rlf\\n;flag");
+        assert_eq!(tags.get("a"), Some("one;two"));
+        assert_eq!(tags.get("b"), Some("x y"));
+        assert_eq!(tags.get("c"), Some("back\\slash"));
+        assert_eq!(tags.get("d"), Some("cr\rlf\n"));
+        assert_eq!(tags.get("flag"), Some(""));
+        assert_eq!(tags.get("missing"), None);
+    }
+
+    #[test]
+    fn lone_trailing_backslash_is_dropped() {
+        assert_eq!(Tags::parse("a=value\\").get("a"), Some("value"));
+    }
+
+    #[test]
+    fn unknown_escape_is_the_character_itself() {
+        assert_eq!(Tags::parse("a=\\x\\b").get("a"), Some("xb"));
+    }
+
+    #[test]
+    fn escape_round_trip() {
+        let value = "semi;colon space back\\slash cr\r lf\n";
+        let tags = Tags::new().with("+draft/reply", value).with("+typing", "active");
+        let line = tags.to_string();
+        assert_eq!(line, "+draft/reply=semi\\:colon\\sspace\\sback\\\\slash\\scr\\r\\slf\\n;+typing=active");
+        assert_eq!(Tags::parse(&line), tags);
+    }
+
+    #[test]
+    fn insert_replaces_and_empty_values_have_no_equals() {
+        let mut tags = Tags::new().with("+flag", "").with("key", "old");
+        tags.insert("key", "new");
+        assert_eq!(tags.to_string(), "+flag;key=new");
+    }
+}
@@ -0,0 +1,226 @@
+// Turns what is typed in the terminal into lines for the server.
+//
+// Plain text goes to the active channel as a PRIVMSG. Slash commands:
+//   /join <channel> [key]      join and make it the active channel
+//   /part [channel] [reason]
+//   /msg <target> <text>
+//   /me <text>                 action in the active channel
+//   /notice <target> <text>
+//   /whisper <nick> <text>     Buzzen private message in the active channel
+//   /nick <newnick>
+//   /mode <target> [modes]
+//   /kick [channel] <nick> [reason]
+//   /topic [channel] [text]
+//   /away [message]            no message marks you as back
+//   /quit [message]
+//   /raw <line>
+//   /reload                    read the config file again, see reload.rs
+//   /network <name>            talk on another network, when there are several
+
+use crate::event::is_channel;
+
+pub struct Terminal {
+    active: String,
+    quitting: bool,
+    reloading: bool,
+    // every network with the channel that was active on it, see add_network
+    networks: Vec<(String, String)>,
+    network: String,
+}
+
+impl Terminal {
+    pub fn new(active: &str) -> Self {
+        Terminal { active: active.to_string(), quitting: false, reloading: false, networks: Vec::new(), network: String::new() }
+    }
+
+    pub fn active(&self) -> &str {
+        &self.active
+    }
+
+    // Another network to talk on, with its first channel. The first one added
+    // is where input goes until /network says otherwise.
+    pub fn add_network(&mut self, name: &str, channel: &str) {
+        if self.networks.is_empty() {
+            self.network = name.to_string();
+            self.active = channel.to_string();
+        }
+        self.networks.push((name.to_string(), channel.to_string()));
+    }
+
+    // the network lines from handle() are meant for
+    pub fn network(&self) -> &str {
+        &self.network
+    }
+
+    // true once /quit has been sent
+    pub fn is_quitting(&self) -> bool {
+        self.quitting
+    }
+
+    // true once after /reload, for whoever reads the config file
+    pub fn take_reload(&mut self) -> bool {
+        std::mem::take(&mut self.reloading)
+    }
+
+    // The line to send for this input, None when there is nothing to send,
+    // or a message explaining what was wrong with it.
+    pub fn handle(&mut self, input: &str) -> Result<Option<String>, String> {
+        let input = input.trim_end_matches(['\r', '\n']);
+        if input.trim().is_empty() {
+            return Ok(None);
+        }
+
+        let Some(command_line) = input.strip_prefix('/') else {
+            let channel = self.require_active()?;
+            return Ok(Some(format!("PRIVMSG {} :{}", channel, input)));
+        };
+        // "//text" sends text starting with a slash
+        if command_line.starts_with('/') {
+            let channel = self.require_active()?;
+            return Ok(Some(format!("PRIVMSG {} :{}", channel, command_line)));
+        }
+
+        let (command, args) = command_line.split_once(' ').unwrap_or((command_line, ""));
+        let args = args.trim();
+        let (first, rest) = split_first(args);
+
+        let line = match command.to_lowercase().as_str() {
+            "join" | "j" => {
+                if first.is_empty() {
+                    return Err("Usage: /join <channel> [key]".to_string());
+                }
+                self.active = first.to_string();
+                if rest.is_empty() {
+                    format!("JOIN {}", first)
+                } else {
+                    format!("JOIN {} {}", first, rest)
+                }
+            },
+            "part" | "leave" => {
+                let (channel, reason) = if is_channel(first) { (first.to_string(), rest) } else { (self.require_active()?, args) };
+                if reason.is_empty() {
+                    format!("PART {}", channel)
+                } else {
+                    format!("PART {} :{}", channel, reason)
+                }
+            },
+            "msg" | "privmsg" => {
+                if first.is_empty() || rest.is_empty() {
+                    return Err("Usage: /msg <target> <text>".to_string());
+                }
+                format!("PRIVMSG {} :{}", first, rest)
+            },
+            "me" => {
+                let channel = self.require_active()?;
+                format!("PRIVMSG {} :\u{0001}ACTION {}\u{0001}", channel, args)
+            },
+            "notice" => {
+                if first.is_empty() || rest.is_empty() {
+                    return Err("Usage: /notice <target> <text>".to_string());
+                }
+                format!("NOTICE {} :{}", first, rest)
+            },
+            "whisper" | "w" => {
+                if first.is_empty() || rest.is_empty() {
+                    return Err("Usage: /whisper <nick> <text>".to_string());
+                }
+                let channel = self.require_active()?;
+                format!("WHISPER {} {} :{}", channel, first, rest)
+            },
+            "nick" => {
+                if first.is_empty() {
+                    return Err("Usage: /nick <newnick>".to_string());
+                }
+                format!("NICK {}", first)
+            },
+            "mode" => {
+                if first.is_empty() {
+                    return Err("Usage: /mode <target> [modes]".to_string());
+                }
+                format!("MODE {}", args)
+            },
+            "kick" => {
+                let (channel, args) = if is_channel(first) { (first.to_string(), rest) } else { (self.require_active()?, args) };
+                let (nick, reason) = split_first(args);
+                if nick.is_empty() {
+                    return Err("Usage: /kick [channel] <nick> [reason]".to_string());
+                }
+                if reason.is_empty() {
+                    format!("KICK {} {}", channel, nick)
+                } else {
+                    format!("KICK {} {} :{}", channel, nick, reason)
+                }
+            },
+            "topic" => {
+                let (channel, topic) = if is_channel(first) { (first.to_string(), rest) } else { (self.require_active()?, args) };
+                if topic.is_empty() {
+                    format!("TOPIC {}", channel)
+                } else {
+                    format!("TOPIC {} :{}", channel, topic)
+                }
+            },
+            "away" => {
+                if args.is_empty() {
+                    "AWAY".to_string()
+                } else {
+                    format!("AWAY :{}", args)
+                }
+            },
+            "quit" | "exit" => {
+                self.quitting = true;
+                if args.is_empty() {
+                    "QUIT".to_string()
+                } else {
+                    format!("QUIT :{}", args)
+                }
+            },
+            "network" | "net" => {
+                self.switch_network(first)?;
+                return Ok(None);
+            },
+            "reload" => {
+                self.reloading = true;
+                return Ok(None);
+            },
+            "raw" | "quote" => {
+                if args.is_empty() {
+                    return Err("Usage: /raw <line>".to_string());
+                }
+                args.to_string()
+            },
+            _ => return Err(format!("Unknown command: /{}", command)),
+        };
+        Ok(Some(line))
+    }
+
+    fn switch_network(&mut self, name: &str) -> Result<(), String> {
+        let names: Vec<&str> = self.networks.iter().map(|(network, _)| network.as_str()).collect();
+        let Some(index) = self.networks.iter().position(|(network, _)| network.eq_ignore_ascii_case(name)) else {
+            return Err(format!("Usage: /network <name>, one of: {} (now on {})", names.join(", "), self.network));
+        };
+        // remember where we were for coming back
+        let active = std::mem::take(&mut self.active);
+        if let Some((_, channel)) = self.networks.iter_mut().find(|(network, _)| *network == self.network) {
+            *channel = active;
+        }
+        let (network, channel) = &self.networks[index];
+        self.network = network.clone();
+        self.active = channel.clone();
+        Ok(())
+    }
+
+    fn require_active(&self) -> Result<String, String> {
+        if self.active.is_empty() {
+            Err("No active channel, use /join <channel> first".to_string())
+        } else {
+            Ok(self.active.clone())
+        }
+    }
+}
+
+fn split_first(args: &str) -> (&str, &str) {
+    match args.split_once(' ') {
+        Some((first, rest)) => (first, rest.trim_start()),
+        None => (args, ""),
+    }
+}
@@ -0,0 +1,161 @@
+// What the client talks over: a plain TcpStream, or TLS on top of one.
+//
+// IrcClient only needs something it can read and write, so any Transport
+// works (see IrcClient::attach). connect() picks one from the config, going
+// through a proxy first if one is set (proxy.rs).
+
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::{fs, io};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use tokio_rustls::rustls::crypto::{self, ring, CryptoProvider};
+use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use tokio_rustls::rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use tokio_rustls::TlsConnector;
+
+use crate::proxy::{self, ProxyConfig};
+
+pub trait Transport: AsyncRead + AsyncWrite + Send + Unpin + 'static {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> Transport for T {}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TlsConfig {
+    pub enabled: bool,
+    // PEM file with extra CA certificates, trusted on top of the system roots
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ca_file: Option<String>,
+    // accept any certificate, only for testing against a self-signed server
+    pub insecure_skip_verify: bool,
+    // PEM certificate and key to identify ourselves with, e.g. for SASL EXTERNAL
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_cert: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_key: Option<String>,
+}
+
+// Open a connection to server ("host:port"), with TLS if it is enabled.
+pub async fn connect(server: &str, tls: &TlsConfig, proxy: Option<&ProxyConfig>) -> io::Result<Box<dyn Transport>> {
+    let (host, port) = split_server(server)?;
+    let stream = match proxy {
+        Some(proxy) => {
+            let mut stream = TcpStream::connect((proxy.host.as_str(), proxy.port)).await?;
+            proxy::handshake(&mut stream, proxy, host, port).await?;
+            stream
+        },
+        None => TcpStream::connect((host, port)).await?,
+    };
+    if !tls.enabled {
+        return Ok(Box::new(stream));
+    }
+
+    let connector = TlsConnector::from(Arc::new(client_config(tls)?));
+    let name = ServerName::try_from(host.to_string())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid server name {}: {}", host, err)))?;
+    Ok(Box::new(connector.connect(name, stream).await?))
+}
+
+// "irc.example.com:6667" or "[::1]:6667" into host and port
+pub(crate) fn split_server(server: &str) -> io::Result<(&str, u16)> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidInput, format!("Server should be host:port, got {:?}", server));
+    let (host, port) = server.rsplit_once(':').ok_or_else(invalid)?;
+    let port = port.parse().map_err(|_| invalid())?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    // port 0 can't be connected to
+    if host.is_empty() || port == 0 {
+        return Err(invalid());
+    }
+    Ok((host, port))
+}
+
+fn client_config(tls: &TlsConfig) -> io::Result<ClientConfig> {
+    let provider = Arc::new(ring::default_provider());
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(io::Error::other)?;
+
+    let builder = if tls.insecure_skip_verify {
+        builder.dangerous().with_custom_certificate_verifier(Arc::new(SkipVerify(provider)))
+    } else {
+        builder.with_root_certificates(root_store(tls)?)
+    };
+    match (&tls.client_cert, &tls.client_key) {
+        (Some(cert_file), Some(key_file)) => {
+            let certs = rustls_pemfile::certs(&mut read_pem(cert_file)?.as_slice()).collect::<Result<Vec<_>, _>>()?;
+            let key = rustls_pemfile::private_key(&mut read_pem(key_file)?.as_slice())?
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("No private key in {}", key_file)))?;
+            builder
+                .with_client_auth_cert(certs, key)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("Bad client certificate: {}", err)))
+        },
+        (None, None) => Ok(builder.with_no_client_auth()),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "tls.client_cert and tls.client_key go together")),
+    }
+}
+
+fn read_pem(file: &str) -> io::Result<Vec<u8>> {
+    fs::read(file).map_err(|err| io::Error::new(err.kind(), format!("Could not read {}: {}", file, err)))
+}
+
+fn root_store(tls: &TlsConfig) -> io::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    // a few unreadable system certificates are not worth failing over
+    let native = rustls_native_certs::load_native_certs();
+    roots.add_parsable_certificates(native.certs);
+
+    if let Some(ca_file) = &tls.ca_file {
+        for cert in rustls_pemfile::certs(&mut read_pem(ca_file)?.as_slice()) {
+            roots
+                .add(cert?)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("Bad certificate in {}: {}", ca_file, err)))?;
+        }
+    }
+
+    if roots.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "No trusted CA certificates found, set tls.ca_file"));
+    }
+    Ok(roots)
+}
+
+// Accepts any certificate. Signatures are still checked, so the handshake
+// itself is sound, but anyone can pretend to be the server.
+#[derive(Debug)]
+struct SkipVerify(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for SkipVerify {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, tokio_rustls::rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        crypto::verify_tls12_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        crypto::verify_tls13_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
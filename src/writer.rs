@@ -0,0 +1,142 @@
+// The writing half of the connection. Drains the queue behind every Sender
+// and writes the lines to the server in order, so nothing has to wait for
+// the read loop to send. Lines are paced by the token bucket; PING and PONG
+// go out right away.
+//
+// The queue outlives the connection: each new writer task locks it for as
+// long as it runs, so lines queued while reconnecting are sent afterwards,
+// once the server has welcomed us. Before that the server would turn them
+// away with 451, so only the client's own login lines go out.
+
+use std::io;
+use std::sync::{atomic::Ordering, Arc};
+use tokio::{io::{AsyncWrite, AsyncWriteExt}, sync::Mutex};
+
+use crate::event::Event;
+use crate::handler::Dispatcher;
+use crate::ratelimit::{RateLimit, TokenBucket};
+use crate::sender::Outbound;
+
+pub(crate) async fn run<W: AsyncWrite + Unpin>(
+    mut writer: W,
+    outbound: Arc<Mutex<Outbound>>,
+    limit: RateLimit,
+    dispatcher: Dispatcher,
+) -> io::Result<()> {
+    let mut guard = outbound.lock().await;
+    let outbound = &mut *guard;
+    let mut registered = outbound.registered.subscribe();
+    // whatever the client queued for itself was meant for the old connection
+    while outbound.own.try_recv().is_ok() {}
+    let mut bucket = TokenBucket::new(limit);
+    loop {
+        let held = !*registered.borrow();
+        let (line, queued) = tokio::select! {
+            biased;
+            Some(pong) = outbound.priority.recv() => {
+                writer.write_all(format!("{}\n", pong).as_bytes()).await?;
+                continue;
+            },
+            Some(line) = outbound.own.recv() => (line, false),
+            // the last writer took it but never got to write it
+            Some(line) = std::future::ready(outbound.in_flight.clone()), if !held => (line, true),
+            line = outbound.queue.recv(), if !held => match line {
+                Some(line) => {
+                    outbound.depth.fetch_sub(1, Ordering::Relaxed);
+                    outbound.in_flight = Some(line.clone());
+                    (line, true)
+                },
+                None => return Ok(()),
+            },
+            Ok(()) = registered.changed(), if held => continue,
+            else => return Ok(()),
+        };
+
+        // wait for enough tokens, still answering PINGs in the meantime
+        let cost = bucket.cost(&line);
+        loop {
+            let wait = bucket.wait_time(cost);
+            if wait.is_zero() {
+                break;
+            }
+            tokio::select! {
+                biased;
+                Some(pong) = outbound.priority.recv() => {
+                    writer.write_all(format!("{}\n", pong).as_bytes()).await?;
+                },
+                _ = tokio::time::sleep(wait) => {},
+            }
+        }
+        bucket.take(cost);
+
+        dispatcher.emit(Event::Sent { line: mask(&line) }).await;
+        writer.write_all(format!("{}\n", line).as_bytes()).await?;
+        if queued {
+            outbound.in_flight = None;
+        }
+        if line.to_uppercase().starts_with("QUIT") {
+            outbound.quit.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+// Write lines straight to the server, ahead of anything queued. Used for the
+// login handshake right after connecting.
+pub(crate) async fn write_now<W: AsyncWrite + Unpin>(writer: &mut W, lines: &[String], dispatcher: &Dispatcher) -> io::Result<()> {
+    for line in lines {
+        dispatcher.emit(Event::Sent { line: mask(line) }).await;
+        writer.write_all(format!("{}\n", line).as_bytes()).await?;
+    }
+    Ok(())
+}
+
+// keep passwords out of the terminal
+fn mask(line: &str) -> String {
+    if line.starts_with("LOGIN") {
+        "LOGINH ********** ***********".to_string()
+    } else if line.starts_with("PASS") {
+        "PASS **********".to_string()
+    } else if line.strip_prefix("AUTHENTICATE ").is_some_and(|data| data.len() > 1 && !data.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '-')) {
+        // SASL payloads; mechanism names, "+" and "*" are fine to show
+        "AUTHENTICATE **********".to_string()
+    } else {
+        line.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::caps::Caps;
+    use crate::handler::Context;
+    use crate::sender::Sender;
+    use crate::tags::Tags;
+    use std::time::Duration;
+    use tokio::io::{duplex, AsyncBufReadExt, BufReader};
+
+    #[tokio::test(start_paused = true)]
+    async fn line_waiting_for_tokens_survives_an_abort() {
+        let (sender, outbound) = Sender::new();
+        outbound.registered.send_replace(true);
+        let outbound = Arc::new(Mutex::new(outbound));
+        let dispatcher = Dispatcher::new(Context { network: "test".to_string(), sender: sender.clone(), caps: Caps::default(), tags: Tags::new() });
+        // one line, then a very long wait for the next
+        let limit = RateLimit { burst: 1.0, refill_per_second: 0.001, per_byte_penalty: 0.0 };
+        sender.send("PRIVMSG #a :one").unwrap();
+        sender.send("PRIVMSG #a :two").unwrap();
+
+        let (stream, server) = duplex(4096);
+        let writer = tokio::spawn(run(stream, outbound.clone(), limit.clone(), dispatcher.clone()));
+        let mut lines = BufReader::new(server).lines();
+        assert_eq!(lines.next_line().await.unwrap().unwrap(), "PRIVMSG #a :one");
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert_eq!(sender.queue_depth(), 0);
+        writer.abort();
+        let _ = writer.await;
+
+        let (stream, server) = duplex(4096);
+        tokio::spawn(run(stream, outbound, limit, dispatcher));
+        let mut lines = BufReader::new(server).lines();
+        assert_eq!(lines.next_line().await.unwrap().unwrap(), "PRIVMSG #a :two");
+    }
+}